@@ -1,21 +1,34 @@
-use bytes::{BufMut, BytesMut};
+use std::sync::Arc;
+
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
 use tokio_util::codec::{Decoder, Encoder};
 
-use crate::{SigmaRequest, SigmaResponse};
+use crate::util::{message_buf_for_prefix, peek_message_len_impl};
+use crate::{DecodeOptions, SigmaRequest, SigmaResponse};
 
 /// Errors of [`tokio_util::codec::Framed`] stream with [`SigmaClientProtocol`] codec.
 #[derive(Debug, thiserror::Error)]
 pub enum ClientProtocolError {
     #[error(transparent)]
     ExtfgSigma(#[from] crate::Error),
+    #[deprecated(
+        note = "not produced anymore, malformed length prefix is reported as `ExtfgSigma(Error::IncorrectMessageLength)`"
+    )]
     #[error(transparent)]
     WrongLenUtf8(#[from] std::str::Utf8Error),
+    #[deprecated(
+        note = "not produced anymore, malformed length prefix is reported as `ExtfgSigma(Error::IncorrectMessageLength)`"
+    )]
     #[error(transparent)]
     WrongLenInt(#[from] std::num::ParseIntError),
     #[error(transparent)]
     StdIoError(#[from] std::io::Error),
+    #[error("Message length {len} exceeds maximum of {max}")]
+    MessageTooLong { len: usize, max: usize },
 }
 
+#[allow(deprecated)]
 impl PartialEq for ClientProtocolError {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -25,56 +38,252 @@ impl PartialEq for ClientProtocolError {
             (Self::ExtfgSigma(x), Self::ExtfgSigma(y)) => x == y,
             (Self::WrongLenUtf8(x), Self::WrongLenUtf8(y)) => x == y,
             (Self::WrongLenInt(x), Self::WrongLenInt(y)) => x == y,
+            (
+                Self::MessageTooLong { len, max },
+                Self::MessageTooLong {
+                    len: other_len,
+                    max: other_max,
+                },
+            ) => len == other_len && max == other_max,
             (_, _) => false,
         }
     }
 }
 
-pub const LENGTH_BYTES_COUNT: usize = 5;
+pub use crate::LENGTH_BYTES_COUNT;
+
+/// Errors of [`tokio_util::codec::Framed`] stream with [`SigmaServerProtocol`] codec.
+pub type ServerProtocolError = ClientProtocolError;
+
+/// Splits single length-prefixed message from `src`, reserving space for the rest of it if it is incomplete.
+///
+/// Messages with declared length above `max_message_len` are rejected before buffering their data.
+fn decode_frame(
+    src: &mut BytesMut,
+    max_message_len: Option<usize>,
+    len_width: usize,
+) -> Result<Option<BytesMut>, ClientProtocolError> {
+    let current_length = src.len();
+
+    let overall_length = match peek_message_len_impl(src, len_width, true)? {
+        Some(len) => len,
+        None => {
+            src.reserve(len_width - current_length);
+            return Ok(None);
+        }
+    };
+
+    let msg_len = overall_length - len_width;
+    if let Some(max) = max_message_len {
+        if msg_len > max {
+            return Err(ClientProtocolError::MessageTooLong { len: msg_len, max });
+        }
+    }
+
+    Ok(match current_length < overall_length {
+        true => {
+            src.reserve(overall_length - current_length);
+            None
+        }
+        false => Some(src.split_to(overall_length)),
+    })
+}
+
+/// Reads single length-prefixed message from `r`, including the prefix.
+async fn read_frame<R: AsyncRead + Unpin + ?Sized>(
+    r: &mut R,
+) -> Result<Bytes, ClientProtocolError> {
+    let mut len = [0u8; LENGTH_BYTES_COUNT];
+    r.read_exact(&mut len).await?;
+
+    let mut frame = message_buf_for_prefix(len)?;
+    r.read_exact(&mut frame[LENGTH_BYTES_COUNT..]).await?;
+    Ok(frame.into())
+}
+
+impl SigmaResponse {
+    /// Reads and decodes single response from `r` without setting up [`tokio_util::codec::Framed`].
+    pub async fn read_from<R: AsyncRead + Unpin + ?Sized>(
+        r: &mut R,
+    ) -> Result<Self, ClientProtocolError> {
+        Ok(Self::decode(read_frame(r).await?)?)
+    }
+}
+
+impl SigmaRequest {
+    /// Reads and decodes single request from `r` without setting up [`tokio_util::codec::Framed`].
+    pub async fn read_from<R: AsyncRead + Unpin + ?Sized>(
+        r: &mut R,
+    ) -> Result<Self, ServerProtocolError> {
+        Ok(Self::decode(read_frame(r).await?)?)
+    }
+}
 
 /// Codec for semi-automated encoding/decoding of [`SigmaRequest`]s and [`SigmaResponse`]s.
+///
+/// See [`SigmaClientProtocol::with_max_len`] and [`SigmaClientProtocol::len_width`] for codecs with non-default settings.
+#[derive(Debug, Clone, Copy, Default)]
 pub struct SigmaClientProtocol;
 
-impl Decoder for SigmaClientProtocol {
-    type Item = SigmaResponse;
-    type Error = ClientProtocolError;
+/// Server side counterpart of [`SigmaClientProtocol`]: decodes [`SigmaRequest`]s and encodes [`SigmaResponse`]s.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigmaServerProtocol;
 
-    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let current_length = src.len();
+/// [`SigmaClientProtocol`] or [`SigmaServerProtocol`] with non-default message length limit or length prefix width.
+#[derive(Debug, Clone, Copy)]
+pub struct ConfiguredProtocol<P> {
+    protocol: P,
+    settings: Settings,
+}
 
-        if current_length < LENGTH_BYTES_COUNT {
-            src.reserve(LENGTH_BYTES_COUNT - current_length);
-            return Ok(None);
+#[derive(Debug, Clone, Copy)]
+struct Settings {
+    max_message_len: Option<usize>,
+    len_width: usize,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            max_message_len: None,
+            len_width: LENGTH_BYTES_COUNT,
         }
+    }
+}
 
-        let msg_len = std::str::from_utf8(&src[0..LENGTH_BYTES_COUNT])
-            .map_err(ClientProtocolError::from)?
-            .parse::<usize>()
-            .map_err(ClientProtocolError::from)?;
+impl<P> ConfiguredProtocol<P> {
+    fn new(protocol: P) -> Self {
+        Self {
+            protocol,
+            settings: Settings::default(),
+        }
+    }
 
-        let overall_length = msg_len + LENGTH_BYTES_COUNT;
+    /// Fails with [`ClientProtocolError::MessageTooLong`] on messages longer than `max_message_len` bytes (excluding the length prefix).
+    pub fn max_len(mut self, max_message_len: usize) -> Self {
+        self.settings.max_message_len = Some(max_message_len);
+        self
+    }
 
-        Ok(match current_length < overall_length {
-            true => {
-                src.reserve(overall_length - current_length);
-                None
-            }
-            false => Some(SigmaResponse::decode(src.split_to(overall_length).into())?),
-        })
+    /// Sets number of digits in the message length prefix, [`LENGTH_BYTES_COUNT`] by default.
+    pub fn len_width(mut self, len_width: usize) -> Self {
+        self.settings.len_width = len_width;
+        self
+    }
+
+    /// Codec with default settings, which this one is based on.
+    pub fn protocol(&self) -> &P {
+        &self.protocol
+    }
+
+    fn settings(&self) -> Settings {
+        self.settings
+    }
+}
+
+impl SigmaClientProtocol {
+    fn settings(&self) -> Settings {
+        Settings::default()
+    }
+
+    /// Creates codec which fails with [`ClientProtocolError::MessageTooLong`] on messages longer than `max_message_len` bytes (excluding the length prefix).
+    pub fn with_max_len(max_message_len: usize) -> ConfiguredProtocol<Self> {
+        ConfiguredProtocol::new(Self).max_len(max_message_len)
+    }
+
+    /// Creates codec with length prefix of `len_width` digits instead of [`LENGTH_BYTES_COUNT`].
+    pub fn len_width(self, len_width: usize) -> ConfiguredProtocol<Self> {
+        ConfiguredProtocol::new(self).len_width(len_width)
     }
 }
 
-impl Encoder<SigmaRequest> for SigmaClientProtocol {
-    type Error = ClientProtocolError;
+impl SigmaServerProtocol {
+    fn settings(&self) -> Settings {
+        Settings::default()
+    }
+
+    /// Creates codec which fails with [`ClientProtocolError::MessageTooLong`] on messages longer than `max_message_len` bytes (excluding the length prefix).
+    pub fn with_max_len(max_message_len: usize) -> ConfiguredProtocol<Self> {
+        ConfiguredProtocol::new(Self).max_len(max_message_len)
+    }
 
-    fn encode(&mut self, item: SigmaRequest, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        dst.put(item.encode()?);
-        Ok(())
+    /// Creates codec with length prefix of `len_width` digits instead of [`LENGTH_BYTES_COUNT`].
+    pub fn len_width(self, len_width: usize) -> ConfiguredProtocol<Self> {
+        ConfiguredProtocol::new(self).len_width(len_width)
     }
 }
 
+/// Implements `Decoder` of `$decoded` and `Encoder`s of owned, borrowed and `Arc`-wrapped `$encoded` for codec `$t`.
+macro_rules! impl_codec {
+    ($t:ty, $decoded:ty, $encoded:ty) => {
+        impl Decoder for $t {
+            type Item = $decoded;
+            type Error = ClientProtocolError;
+
+            fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+                let settings = self.settings();
+                match decode_frame(src, settings.max_message_len, settings.len_width)? {
+                    Some(frame) => Ok(Some(<$decoded>::decode_with_options(
+                        frame.freeze(),
+                        DecodeOptions {
+                            len_width: settings.len_width,
+                            trim_len_prefix: true,
+                            ..Default::default()
+                        },
+                    )?)),
+                    None => Ok(None),
+                }
+            }
+        }
+
+        impl Encoder<$encoded> for $t {
+            type Error = ClientProtocolError;
+
+            fn encode(&mut self, item: $encoded, dst: &mut BytesMut) -> Result<(), Self::Error> {
+                self.encode(&item, dst)
+            }
+        }
+
+        impl Encoder<&$encoded> for $t {
+            type Error = ClientProtocolError;
+
+            fn encode(&mut self, item: &$encoded, dst: &mut BytesMut) -> Result<(), Self::Error> {
+                item.encode_to_impl(dst, false, self.settings().len_width)?;
+                Ok(())
+            }
+        }
+
+        impl Encoder<Arc<$encoded>> for $t {
+            type Error = ClientProtocolError;
+
+            fn encode(
+                &mut self,
+                item: Arc<$encoded>,
+                dst: &mut BytesMut,
+            ) -> Result<(), Self::Error> {
+                self.encode(item.as_ref(), dst)
+            }
+        }
+    };
+}
+
+impl_codec!(SigmaClientProtocol, SigmaResponse, SigmaRequest);
+impl_codec!(
+    ConfiguredProtocol<SigmaClientProtocol>,
+    SigmaResponse,
+    SigmaRequest
+);
+impl_codec!(SigmaServerProtocol, SigmaRequest, SigmaResponse);
+impl_codec!(
+    ConfiguredProtocol<SigmaServerProtocol>,
+    SigmaRequest,
+    SigmaResponse
+);
+
 #[cfg(test)]
 mod tests {
+    use bytes::BufMut;
+
     use super::*;
 
     #[test]
@@ -126,4 +335,201 @@ mod tests {
         assert!(matches!(SigmaClientProtocol.decode(&mut buf), Ok(Some(_))));
         assert_eq!(buf, b""[..]);
     }
+
+    #[test]
+    fn server_decode_incomplete_data() {
+        const DATA: &[u8] = b"00016YM02000000000";
+        let mut buf = BytesMut::new();
+        buf.put(DATA);
+
+        assert!(matches!(SigmaServerProtocol.decode(&mut buf), Ok(None)));
+        assert_eq!(buf, DATA);
+    }
+
+    #[test]
+    fn server_decode_complete_data() {
+        const DATA: &[u8] = b"00016YM02000000000001";
+        let mut buf = BytesMut::new();
+        buf.put(DATA);
+
+        let req = SigmaServerProtocol.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(req.mti(), "0200");
+        assert_eq!(req.auth_serno, 1);
+        assert_eq!(buf, b""[..]);
+    }
+
+    #[test]
+    fn server_encode_response() {
+        let resp = SigmaResponse::new("0110", 4007040978, 8495).unwrap();
+        let mut buf = BytesMut::new();
+
+        SigmaServerProtocol.encode(resp, &mut buf).unwrap();
+        assert_eq!(buf, b"0002401104007040978T\x00\x31\x00\x00\x048495"[..]);
+    }
+
+    #[test]
+    fn decode_max_len() {
+        const DATA: &[u8] = b"0002401104007040978T\x00\x31\x00\x00\x048495";
+        let mut buf = BytesMut::new();
+        buf.put(DATA);
+
+        assert!(matches!(
+            SigmaClientProtocol::with_max_len(24).decode(&mut buf),
+            Ok(Some(_))
+        ));
+
+        buf.put(&b"00025"[..]);
+        assert_eq!(
+            SigmaClientProtocol::with_max_len(24)
+                .decode(&mut buf)
+                .unwrap_err(),
+            ClientProtocolError::MessageTooLong { len: 25, max: 24 }
+        );
+    }
+
+    #[test]
+    fn encode_pipelined() {
+        let mut buf = BytesMut::new();
+        let mut client = SigmaClientProtocol;
+        client
+            .encode(SigmaRequest::new("Y", "M", "0200", 1).unwrap(), &mut buf)
+            .unwrap();
+        client
+            .encode(SigmaRequest::new("N", "O", "0120", 2).unwrap(), &mut buf)
+            .unwrap();
+
+        let mut server = SigmaServerProtocol;
+        assert_eq!(server.decode(&mut buf).unwrap().unwrap().auth_serno, 1);
+        assert_eq!(server.decode(&mut buf).unwrap().unwrap().auth_serno, 2);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn custom_len_width() {
+        for len_width in [4, 6] {
+            let mut buf = BytesMut::new();
+            SigmaClientProtocol
+                .len_width(len_width)
+                .encode(SigmaRequest::new("Y", "M", "0200", 1).unwrap(), &mut buf)
+                .unwrap();
+            assert_eq!(
+                &buf[..len_width],
+                format!("{:0w$}", 16, w = len_width).as_bytes()
+            );
+
+            let mut server = SigmaServerProtocol.len_width(len_width);
+            let req = server.decode(&mut buf).unwrap().unwrap();
+            assert_eq!(req.auth_serno, 1);
+            assert!(buf.is_empty());
+
+            server
+                .encode(SigmaResponse::new("0210", 1, 8100).unwrap(), &mut buf)
+                .unwrap();
+            let mut client = SigmaClientProtocol::with_max_len(24).len_width(len_width);
+            assert_eq!(
+                &buf[..len_width],
+                format!("{:0w$}", 24, w = len_width).as_bytes()
+            );
+            assert_eq!(client.decode(&mut buf).unwrap().unwrap().reason, 8100);
+            assert!(buf.is_empty());
+        }
+
+        let mut buf = BytesMut::from(&b"0024"[..]);
+        assert!(matches!(
+            SigmaClientProtocol.len_width(4).decode(&mut buf),
+            Ok(None)
+        ));
+    }
+
+    fn block_on<F: std::future::Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(f)
+    }
+
+    #[test]
+    fn read_from_async_reader() {
+        let mut src: &[u8] = b"0002401104007040978T\x00\x31\x00\x00\x04849500016YM02000000000001";
+        let resp = block_on(SigmaResponse::read_from(&mut src)).unwrap();
+        assert_eq!(resp.auth_serno, 4007040978);
+        assert_eq!(resp.reason, 8495);
+        let req = block_on(SigmaRequest::read_from(&mut src)).unwrap();
+        assert_eq!(req.auth_serno, 1);
+        assert!(src.is_empty());
+
+        let mut src: &[u8] = b"0002401104007040978T\x00\x31";
+        assert!(matches!(
+            block_on(SigmaResponse::read_from(&mut src)),
+            Err(ClientProtocolError::StdIoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn decode_wrong_length() {
+        let mut buf = BytesMut::from(&b"123 4YM02000000000001"[..]);
+        assert_eq!(
+            SigmaServerProtocol.decode(&mut buf).unwrap_err(),
+            ClientProtocolError::ExtfgSigma(crate::Error::IncorrectMessageLength(
+                b"123 4".to_vec()
+            ))
+        );
+
+        let mut buf = BytesMut::from(&b"   16YM02000000000001"[..]);
+        assert!(matches!(SigmaServerProtocol.decode(&mut buf), Ok(Some(_))));
+
+        let mut buf = BytesMut::from(&b"16   YM02000000000001"[..]);
+        assert_eq!(
+            SigmaServerProtocol.decode(&mut buf).unwrap_err(),
+            ClientProtocolError::ExtfgSigma(crate::Error::IncorrectMessageLength(
+                b"16   ".to_vec()
+            ))
+        );
+        assert!(SigmaRequest::decode(Bytes::from_static(b"   16YM02000000000001")).is_err());
+    }
+
+    #[test]
+    fn encode_borrowed() {
+        let req = SigmaRequest::new("Y", "M", "0200", 1).unwrap();
+        let mut client = SigmaClientProtocol;
+        let mut buf = BytesMut::new();
+        client.encode(&req, &mut buf).unwrap();
+        client.encode(&req, &mut buf).unwrap();
+        client.encode(Arc::new(req.clone()), &mut buf).unwrap();
+        assert_eq!(
+            buf,
+            b"00016YM0200000000000100016YM0200000000000100016YM02000000000001"[..]
+        );
+
+        let resp = Arc::new(SigmaResponse::new("0210", 1, 8100).unwrap());
+        let mut buf = BytesMut::new();
+        SigmaServerProtocol.encode(resp.clone(), &mut buf).unwrap();
+        assert_eq!(buf, resp.encode().unwrap());
+    }
+
+    #[test]
+    fn decode_max_length_without_body() {
+        let mut buf = BytesMut::from(&b"99999"[..]);
+        assert_eq!(
+            SigmaServerProtocol::with_max_len(8192)
+                .decode(&mut buf)
+                .unwrap_err(),
+            ClientProtocolError::MessageTooLong {
+                len: 99999,
+                max: 8192
+            }
+        );
+        assert!(buf.capacity() < 99999);
+
+        let mut buf = BytesMut::from(&b"99999"[..]);
+        assert_eq!(
+            SigmaClientProtocol::with_max_len(8192)
+                .decode(&mut buf)
+                .unwrap_err(),
+            ClientProtocolError::MessageTooLong {
+                len: 99999,
+                max: 8192
+            }
+        );
+    }
 }
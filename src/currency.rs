@@ -0,0 +1,145 @@
+use alloc::format;
+use alloc::string::String;
+use core::fmt::{Display, Formatter, Result as FmtResult};
+
+use bytes::BytesMut;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// Currency of [`FeeData`](crate::FeeData): ISO 4217 numeric code, or 3 letter alphabetic code used by some partners.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Currency {
+    Numeric(u16),
+    Alpha([u8; 3]),
+}
+
+impl Currency {
+    /// Parses 3 byte currency, either all digits or all ASCII letters.
+    pub fn from_slice(data: &[u8]) -> Result<Self, Error> {
+        match data {
+            [a, b, c] if data.iter().all(u8::is_ascii_digit) => Ok(Currency::Numeric(
+                u16::from(a - b'0') * 100 + u16::from(b - b'0') * 10 + u16::from(c - b'0'),
+            )),
+            [a, b, c] if data.iter().all(u8::is_ascii_alphabetic) => {
+                Ok(Currency::Alpha([*a, *b, *c]))
+            }
+            _ => Err(
+                Error::incorrect_field_data("FeeData.currency", "3 digits or 3 letters").got(data),
+            ),
+        }
+    }
+
+    /// Appends 3 byte wire representation of the currency to `buf`.
+    pub(crate) fn encode_to_buf(&self, buf: &mut BytesMut) -> Result<(), Error> {
+        match self {
+            Currency::Numeric(v) if *v > 999 => Err(Error::Bounds(
+                "FeeData.currency should be less or equal 999".into(),
+            )),
+            Currency::Numeric(v) => {
+                buf.extend_from_slice(format!("{:03}", v).as_bytes());
+                Ok(())
+            }
+            Currency::Alpha(v) if !v.iter().all(u8::is_ascii_alphabetic) => Err(Error::Bounds(
+                "FeeData.currency should be 3 ASCII letters".into(),
+            )),
+            Currency::Alpha(v) => {
+                buf.extend_from_slice(v);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<u16> for Currency {
+    fn from(v: u16) -> Self {
+        Currency::Numeric(v)
+    }
+}
+
+impl PartialEq<u16> for Currency {
+    fn eq(&self, other: &u16) -> bool {
+        *self == Currency::Numeric(*other)
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Currency::Numeric(v) => f.write_fmt(format_args!("{:03}", v)),
+            Currency::Alpha(v) => f.write_str(&String::from_utf8_lossy(v)),
+        }
+    }
+}
+
+/// Numeric currency is serialized as a number and alphabetic one as a string, e.g. `643` and `"USD"`.
+impl Serialize for Currency {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Currency::Numeric(v) => serializer.serialize_u16(*v),
+            Currency::Alpha(v) => serializer.serialize_str(&String::from_utf8_lossy(v)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Numeric(u16),
+            Alpha(String),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Numeric(v) => Ok(Currency::Numeric(v)),
+            Repr::Alpha(v) => match v.as_bytes() {
+                [a, b, c] if v.bytes().all(|b| b.is_ascii_alphabetic()) => {
+                    Ok(Currency::Alpha([*a, *b, *c]))
+                }
+                _ => Err(serde::de::Error::custom(
+                    "currency should be a number or 3 ASCII letters",
+                )),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_currency() {
+        assert_eq!(Currency::from_slice(b"643"), Ok(Currency::Numeric(643)));
+        assert_eq!(Currency::from_slice(b"008"), Ok(Currency::Numeric(8)));
+        assert_eq!(Currency::from_slice(b"USD"), Ok(Currency::Alpha(*b"USD")));
+        assert!(Currency::from_slice(b"US1").is_err());
+        assert!(Currency::from_slice(b"64").is_err());
+        assert!(Currency::from_slice(b"6430").is_err());
+
+        assert_eq!(Currency::Numeric(8).to_string(), "008");
+        assert_eq!(Currency::Alpha(*b"USD").to_string(), "USD");
+    }
+
+    #[test]
+    fn currency_serde() {
+        assert_eq!(
+            serde_json::to_string(&Currency::Numeric(643)).unwrap(),
+            "643"
+        );
+        assert_eq!(
+            serde_json::to_string(&Currency::Alpha(*b"USD")).unwrap(),
+            r#""USD""#
+        );
+        assert_eq!(
+            serde_json::from_str::<Currency>("978").unwrap(),
+            Currency::Numeric(978)
+        );
+        assert_eq!(
+            serde_json::from_str::<Currency>(r#""EUR""#).unwrap(),
+            Currency::Alpha(*b"EUR")
+        );
+        assert!(serde_json::from_str::<Currency>(r#""EURO""#).is_err());
+    }
+}
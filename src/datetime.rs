@@ -0,0 +1,68 @@
+use alloc::format;
+use alloc::string::ToString;
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+
+use crate::{Error, SigmaRequest};
+
+impl SigmaRequest {
+    /// Sets ISO fields 7 (transmission date and time, `MMDDhhmmss`), 12 (local time, `hhmmss`)
+    /// and 13 (local date, `MMDD`) from `dt`.
+    pub fn set_transmission_datetime(&mut self, dt: NaiveDateTime) {
+        let date = format!("{:02}{:02}", dt.month(), dt.day());
+        let time = format!("{:02}{:02}{:02}", dt.hour(), dt.minute(), dt.second());
+        self.iso_fields
+            .insert(7, format!("{}{}", date, time).into());
+        self.iso_fields.insert(12, time.into());
+        self.iso_fields.insert(13, date.into());
+    }
+
+    /// Parses ISO field 7 (`MMDDhhmmss`), which has no year, so it has to be provided.
+    pub fn iso_field_7_datetime(&self, year: i32) -> Result<NaiveDateTime, Error> {
+        let data = self
+            .iso_fields
+            .get(&7)
+            .ok_or_else(|| Error::MissingField("i007".to_string()))?
+            .as_bytes();
+        let incorrect = || Error::incorrect_field_data("i007", "MMDDhhmmss").got(data);
+
+        if data.len() != 10 || !data.iter().all(u8::is_ascii_digit) {
+            return Err(incorrect());
+        }
+        let part = |i: usize| u32::from(data[i] - b'0') * 10 + u32::from(data[i + 1] - b'0');
+
+        NaiveDate::from_ymd_opt(year, part(0), part(2))
+            .and_then(|d| d.and_hms_opt(part(4), part(6), part(8)))
+            .ok_or_else(incorrect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transmission_datetime() {
+        let dt = NaiveDate::from_ymd_opt(2024, 3, 7)
+            .unwrap()
+            .and_hms_opt(9, 5, 1)
+            .unwrap();
+        let mut req = SigmaRequest::new("N", "X", "0100", 1).unwrap();
+        req.set_transmission_datetime(dt);
+
+        assert_eq!(req.iso_fields[&7].as_bytes(), b"0307090501");
+        assert_eq!(req.iso_fields[&12].as_bytes(), b"090501");
+        assert_eq!(req.iso_fields[&13].as_bytes(), b"0307");
+        assert_eq!(req.iso_field_7_datetime(2024).unwrap(), dt);
+
+        req.iso_fields.insert(7, "1332090501".into());
+        assert!(req.iso_field_7_datetime(2024).is_err());
+        req.iso_fields.insert(7, "030709050".into());
+        assert!(req.iso_field_7_datetime(2024).is_err());
+        req.iso_fields.remove(&7);
+        assert_eq!(
+            req.iso_field_7_datetime(2024),
+            Err(Error::MissingField("i007".into()))
+        );
+    }
+}
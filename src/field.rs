@@ -0,0 +1,92 @@
+use core::fmt::{Display, Formatter, Result as FmtResult};
+
+use bytes::{Bytes, BytesMut};
+
+use crate::util::{decode_field_from_cursor, encode_field_to_buf, FIELD_HEADER_LEN};
+use crate::{Error, IsoFieldData, Tag};
+
+/// Number of data bytes shown by [`Field`]'s `Display`.
+const PREVIEW_LEN: usize = 32;
+
+/// Single encoded field: its tag and data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub tag: Tag,
+    pub data: IsoFieldData,
+}
+
+impl Field {
+    pub fn new(tag: Tag, data: impl Into<IsoFieldData>) -> Self {
+        Self {
+            tag,
+            data: data.into(),
+        }
+    }
+
+    /// Encodes the field: its tag, BCD data length and the data.
+    pub fn encode(&self) -> Result<Bytes, Error> {
+        let data = self.data.as_bytes();
+        let mut buf = BytesMut::with_capacity(FIELD_HEADER_LEN + data.len());
+        encode_field_to_buf(self.tag.clone(), data, &mut buf)?;
+        Ok(buf.freeze())
+    }
+
+    /// Decodes single field from the beginning of `buf`, advancing it past the field.
+    pub fn decode(buf: &mut Bytes) -> Result<Self, Error> {
+        let (tag, data) = decode_field_from_cursor(buf)?;
+        Ok(Self {
+            tag,
+            data: IsoFieldData::Bytes(data),
+        })
+    }
+}
+
+/// Shows the tag and up to 32 first bytes of data, with non-printable bytes escaped, e.g. `T0018 "Y"`.
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let data = self.data.as_bytes();
+        f.write_fmt(format_args!("{} \"", self.tag))?;
+        for b in data.iter().take(PREVIEW_LEN) {
+            f.write_fmt(format_args!("{}", core::ascii::escape_default(*b)))?;
+        }
+        f.write_str("\"")?;
+        if data.len() > PREVIEW_LEN {
+            f.write_fmt(format_args!("... ({} bytes)", data.len()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_roundtrip() {
+        let field = Field::new(Tag::Regular(31), "8100");
+        let encoded = field.encode().unwrap();
+        assert_eq!(encoded[..], b"T\x00\x31\x00\x00\x048100"[..]);
+
+        let mut buf = Bytes::from_static(b"T\x00\x31\x00\x00\x048100S\x00\x48\x01\x00\x01X");
+        assert_eq!(Field::decode(&mut buf).unwrap(), field);
+        assert_eq!(
+            Field::decode(&mut buf).unwrap(),
+            Field::new(Tag::IsoSubfield(48, 1), "X")
+        );
+        assert!(buf.is_empty());
+        assert!(Field::decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn field_display() {
+        assert_eq!(Field::new(Tag::Regular(18), "Y").to_string(), "T0018 \"Y\"");
+        assert_eq!(
+            Field::new(Tag::Iso(52), &b"\x01\xff"[..]).to_string(),
+            "i052 \"\\x01\\xff\""
+        );
+        assert_eq!(
+            Field::new(Tag::Iso(2), "5".repeat(40)).to_string(),
+            alloc::format!("i002 \"{}\"... (40 bytes)", "5".repeat(32))
+        );
+    }
+}
@@ -1,5 +1,15 @@
-use std::borrow::Cow;
-use std::collections::BTreeMap;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::convert::TryFrom;
+use core::fmt::{Display, Formatter, Result as FmtResult};
 
 use bytes::{Bytes, BytesMut};
 use serde::{Deserialize, Serialize};
@@ -10,72 +20,347 @@ use crate::util::*;
 #[macro_use]
 mod util;
 
+mod mti;
+pub use mti::Mti;
+
+mod reason;
+pub use reason::{DeclineError, ReasonCode};
+
+mod currency;
+pub use currency::Currency;
+
+mod field;
+pub use field::Field;
+
+mod saf;
+pub use saf::Saf;
+
+mod schema;
+pub use schema::{FieldRule, Schema};
+
+#[cfg(feature = "chrono")]
+mod datetime;
+pub use util::{
+    count_fields, format_length_prefix, hexdump, peek_message_len, peek_message_len_with_width,
+    Tag, TagKind, FIELD_HEADER_LEN, LENGTH_BYTES_COUNT, MAX_AUTH_SERNO, MTI_LEN,
+    REQUEST_HEADER_LEN, RESPONSE_HEADER_LEN, SERNO_LEN,
+};
+#[cfg(feature = "std")]
+pub use util::{gen_random_auth_serno, gen_random_auth_serno_with};
+
 #[cfg(feature = "codec")]
 pub mod codec;
 
-#[derive(Debug, thiserror::Error, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Error {
-    #[error("{0}")]
     Bounds(String),
-    #[error("Incorrect tag: {0}")]
     IncorrectTag(String),
-    #[error("Incorrect field '{field_name}', should be {should_be}")]
     IncorrectFieldData {
         field_name: String,
         should_be: String,
+        /// Escaped and truncated actual value of the field, if known.
+        got: Option<String>,
     },
-    #[error("Missing field '{0}'")]
     MissingField(String),
-    #[error("{0}")]
     IncorrectData(String),
+    DecodeAt {
+        offset: usize,
+        source: Box<Error>,
+    },
+    TagOutOfRange(Tag),
+    /// Message length prefix is not a number, contains the prefix as is.
+    IncorrectMessageLength(Vec<u8>),
+    /// Data is not valid in its text encoding, e.g. base64 or hex.
+    Encoding(String),
+    /// Reading the message failed, see [`SigmaResponse::read_from_sync`].
+    #[cfg(feature = "std")]
+    Io(std::io::ErrorKind),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Bounds(s) => f.write_str(s),
+            Self::IncorrectTag(s) => f.write_fmt(format_args!("Incorrect tag: {}", s)),
+            Self::IncorrectFieldData {
+                field_name,
+                should_be,
+                got,
+            } => {
+                f.write_fmt(format_args!(
+                    "Incorrect field '{}', should be {}",
+                    field_name, should_be
+                ))?;
+                if let Some(got) = got {
+                    f.write_fmt(format_args!(", got '{}'", got))?;
+                }
+                Ok(())
+            }
+            Self::MissingField(s) => f.write_fmt(format_args!("Missing field '{}'", s)),
+            Self::IncorrectData(s) => f.write_str(s),
+            Self::DecodeAt { offset, source } => f.write_fmt(format_args!(
+                "Decoding failed at byte {} of message body: {}",
+                offset, source
+            )),
+            Self::TagOutOfRange(tag) => {
+                f.write_fmt(format_args!("Tag number out of range: {}", tag))
+            }
+            Self::Encoding(s) => f.write_fmt(format_args!("Encoding error: {}", s)),
+            #[cfg(feature = "std")]
+            Self::Io(kind) => f.write_fmt(format_args!("I/O error: {}", kind)),
+            Self::IncorrectMessageLength(len) => f.write_fmt(format_args!(
+                "Message length should be ASCII digits, got {:?}",
+                Bytes::copy_from_slice(len)
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e.kind())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::DecodeAt { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
 }
 
+/// Number of bytes of the actual field value kept in [`Error::IncorrectFieldData`].
+const MAX_GOT_LEN: usize = 32;
+
 impl Error {
+    fn at(self, offset: usize) -> Self {
+        match self {
+            Self::DecodeAt { .. } => self,
+            _ => Self::DecodeAt {
+                offset,
+                source: Box::new(self),
+            },
+        }
+    }
+
     fn incorrect_field_data(field_name: &str, should_be: &str) -> Self {
         Self::IncorrectFieldData {
             field_name: field_name.into(),
             should_be: should_be.into(),
+            got: None,
+        }
+    }
+
+    /// Sets actual value of the field for [`Error::IncorrectFieldData`], escaping non-printable bytes and truncating
+    /// it to [`MAX_GOT_LEN`] bytes.
+    fn got(self, value: impl AsRef<[u8]>) -> Self {
+        match self {
+            Self::IncorrectFieldData {
+                field_name,
+                should_be,
+                ..
+            } => {
+                let value = value.as_ref();
+                let mut got: String = value
+                    .iter()
+                    .take(MAX_GOT_LEN)
+                    .flat_map(|b| core::ascii::escape_default(*b))
+                    .map(char::from)
+                    .collect();
+                if value.len() > MAX_GOT_LEN {
+                    got.push_str("...");
+                }
+                Self::IncorrectFieldData {
+                    field_name,
+                    should_be,
+                    got: Some(got),
+                }
+            }
+            _ => self,
         }
     }
 }
 
 fn validate_mti(s: &str) -> Result<(), Error> {
     let b = s.as_bytes();
-    if b.len() != 4 {
-        return Err(Error::incorrect_field_data(
-            "MTI",
-            "4 digit number (string)",
-        ));
-    }
-    for x in b.iter() {
-        if !x.is_ascii_digit() {
-            return Err(Error::incorrect_field_data(
-                "MTI",
-                "4 digit number (string)",
-            ));
-        }
+    if b.len() != MTI_LEN || !b.iter().all(u8::is_ascii_digit) {
+        return Err(Error::incorrect_field_data("MTI", "4 digit number (string)").got(s));
     }
     Ok(())
 }
 
+/// SRC occupies single byte on the wire, so it has to be single printable ASCII char (`0x20..=0x7E`, space included,
+/// same as [`IsoFieldData::from_ascii`]). Non-ASCII chars are rejected even though they are single `char`s, e.g. `Я` is
+/// 2 bytes in UTF-8.
 fn validate_source(s: &str) -> Result<(), Error> {
-    if s.len() != 1 {
-        return Err(Error::incorrect_field_data("SRC", "single ASCII char"));
+    match s.as_bytes() {
+        [0x20..=0x7e] => Ok(()),
+        _ => Err(Error::incorrect_field_data("SRC", "single printable ASCII char").got(s)),
     }
-    Ok(())
 }
 
 fn validate_saf(s: &str) -> Result<(), Error> {
     match s {
         "Y" | "N" => Ok(()),
-        _ => Err(Error::incorrect_field_data("SAF", "char Y or N")),
+        _ => Err(Error::incorrect_field_data("SAF", "char Y or N").got(s)),
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// Options of [`SigmaRequest::from_json_value_with`] and [`SigmaRequest::from_json_value_with_rng`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct JsonOptions {
+    /// Skip keys which are neither header keys nor tags, e.g. `_comment` added by some tools, instead of failing
+    /// with [`Error::IncorrectTag`].
+    pub ignore_unknown: bool,
+    /// Maximum number of characters of listed tags, longer ones fail with [`Error::IncorrectFieldData`] for the tag.
+    pub max_lengths: BTreeMap<Tag, usize>,
+}
+
+/// Keys of JSON representation of [`SigmaRequest`] which are not tags.
+const JSON_HEADER_KEYS: [&str; 4] = ["SAF", "SRC", "MTI", "Serno"];
+
+/// Parses JSON number or string containing integer.
+fn json_u64(name: &str, value: &Value) -> Result<u64, Error> {
+    match value.as_str() {
+        Some(s) => s
+            .parse::<u64>()
+            .map_err(|_| Error::incorrect_field_data(name, "integer").got(s)),
+        None => value.as_u64().ok_or_else(|| {
+            Error::incorrect_field_data(name, "u64 or String with integer").got(value.to_string())
+        }),
+    }
+}
+
+/// Splits `data` into length-prefixed messages and decodes each of them.
+fn decode_all<T>(
+    mut data: Bytes,
+    decode: impl Fn(Bytes) -> Result<T, Error>,
+) -> Result<Vec<T>, Error> {
+    let mut messages = Vec::new();
+
+    while !data.is_empty() {
+        match peek_message_len(&data)? {
+            Some(len) if len <= data.len() => {
+                messages.push(decode(data.split_to(len))?);
+            }
+            _ => {
+                return Err(Error::IncorrectData(format!(
+                    "Trailing partial message after {} decoded: {} bytes left",
+                    messages.len(),
+                    data.len()
+                )));
+            }
+        }
+    }
+
+    Ok(messages)
+}
+
+fn decode_one<T>(
+    mut data: Bytes,
+    decode: impl Fn(Bytes) -> Result<T, Error>,
+) -> Result<(T, Bytes), Error> {
+    match peek_message_len(&data)? {
+        Some(len) if len <= data.len() => {
+            let message = decode(data.split_to(len))?;
+            Ok((message, data))
+        }
+        _ => Err(Error::IncorrectData(format!(
+            "Partial message: {} bytes",
+            data.len()
+        ))),
+    }
+}
+
+/// Options of [`SigmaRequest::decode_with_options`] and [`SigmaResponse::decode_with_options`].
+///
+/// Default options match [`SigmaRequest::decode`] and [`SigmaResponse::decode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeOptions {
+    /// Stop decoding fields if the rest of the message body consists of ASCII spaces and nulls only,
+    /// for interoperability with peers which pad messages to a block boundary.
+    pub allow_padding: bool,
+    /// Number of digits in the message length prefix, [`LENGTH_BYTES_COUNT`] by default.
+    pub len_width: usize,
+    /// Fail with [`Error::IncorrectData`] if there are bytes left in the input after the message of declared length.
+    pub reject_trailing_bytes: bool,
+    /// Fail with [`Error::IncorrectData`] if the same tag occurs more than once, instead of keeping the last occurrence.
+    ///
+    /// Repeated fee tags (T0032) and fields which [`SigmaResponse`] keeps in `unparsed` are not duplicates.
+    pub reject_duplicate_tags: bool,
+    /// Fail with [`Error::MissingField`] if [`SigmaResponse`] has no reason (T0031), instead of decoding reason as 0.
+    pub require_reason: bool,
+    /// Keep ISO fields, ISO subfields and [`SigmaResponse::adata`] as [`IsoFieldData::Bytes`] slices of the input
+    /// buffer without copying them, instead of [`IsoFieldData::String`] or [`IsoFieldData::Raw`].
+    pub share_buffer: bool,
+    /// Accept length prefix left-padded with spaces, e.g. `   24`, as codecs do. Right padding is rejected anyway.
+    pub trim_len_prefix: bool,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            allow_padding: false,
+            len_width: LENGTH_BYTES_COUNT,
+            reject_trailing_bytes: false,
+            reject_duplicate_tags: false,
+            require_reason: false,
+            share_buffer: false,
+            trim_len_prefix: false,
+        }
+    }
+}
+
+impl DecodeOptions {
+    fn split_message_body(&self, data: &mut Bytes) -> Result<Bytes, Error> {
+        let body = split_message_body(data, self.len_width, self.trim_len_prefix)?;
+        if self.reject_trailing_bytes && !data.is_empty() {
+            return Err(Error::IncorrectData(format!(
+                "Declared message length {} doesn't match the input: {} bytes left after the message",
+                body.len(),
+                data.len()
+            )));
+        }
+        Ok(body)
+    }
+
+    fn check_duplicate(&self, tag: &Tag, duplicate: bool, offset: usize) -> Result<(), Error> {
+        if self.reject_duplicate_tags && duplicate {
+            return Err(Error::IncorrectData(format!(
+                "Duplicate tag {} at byte {} of message body",
+                tag, offset
+            )));
+        }
+        Ok(())
+    }
+
+    fn field_data(&self, data: Bytes) -> IsoFieldData {
+        match self.share_buffer {
+            true => IsoFieldData::Bytes(data),
+            false => IsoFieldData::from_bytes(data),
+        }
+    }
+
+    fn is_padding(&self, data: &[u8]) -> bool {
+        self.allow_padding && data.iter().all(|b| matches!(b, b' ' | b'\0'))
+    }
+}
+
+/// Data of ISO field or subfield.
+///
+/// Values are equal if their bytes are equal, regardless of the variant, e.g. `String("A")` is equal to `Raw(b"A")`
+/// and to `Bytes` containing `A`.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum IsoFieldData {
     String(String),
     Raw(Vec<u8>),
+    /// Slice of the decoded message buffer, shared without copying, see [`DecodeOptions::share_buffer`].
+    Bytes(Bytes),
 }
 
 impl IsoFieldData {
@@ -84,6 +369,16 @@ impl IsoFieldData {
             Self::String(v) => v,
             Self::Raw(v) => String::from_utf8(v)
                 .unwrap_or_else(|err| String::from_utf8_lossy(err.as_bytes()).into_owned()),
+            Self::Bytes(v) => String::from_utf8_lossy(&v).into_owned(),
+        }
+    }
+
+    /// Moves the data out without copying, except for [`IsoFieldData::Bytes`] sharing its buffer with other slices.
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Self::String(v) => v.into_bytes(),
+            Self::Raw(v) => v,
+            Self::Bytes(v) => v.into(),
         }
     }
 
@@ -91,13 +386,41 @@ impl IsoFieldData {
         match self {
             Self::String(ref v) => Cow::Borrowed(v),
             Self::Raw(ref v) => String::from_utf8_lossy(v),
+            Self::Bytes(ref v) => String::from_utf8_lossy(v),
+        }
+    }
+
+    /// Splits data by `sep`, e.g. `USRDT|2595100250` into `USRDT` and `2595100250`.
+    ///
+    /// Parts are borrowed unless data is not valid UTF-8, see [`IsoFieldData::to_cow_str_lossy`].
+    pub fn split_delimited(&self, sep: char) -> Vec<Cow<'_, str>> {
+        match self.to_cow_str_lossy() {
+            Cow::Borrowed(s) => s.split(sep).map(Cow::Borrowed).collect(),
+            Cow::Owned(s) => s.split(sep).map(|v| Cow::Owned(v.to_string())).collect(),
+        }
+    }
+
+    /// Parses ISO field 48 data consisting of `KEY|VALUE|KEY2|VALUE2` tokens into a map, e.g. `USRDT|2595100250` into
+    /// `{"USRDT": "2595100250"}`. Fails with [`Error::IncorrectFieldData`] if the number of tokens is odd.
+    pub fn parse_field48(&self) -> Result<BTreeMap<String, String>, Error> {
+        let parts = self.split_delimited('|');
+        if parts.len() % 2 == 1 {
+            return Err(
+                Error::incorrect_field_data("i048", "KEY|VALUE pairs separated by '|'")
+                    .got(self.as_bytes()),
+            );
         }
+        Ok(parts
+            .chunks(2)
+            .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+            .collect())
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         match self {
             IsoFieldData::String(x) => x.as_bytes(),
             IsoFieldData::Raw(x) => x,
+            IsoFieldData::Bytes(x) => x,
         }
     }
 
@@ -105,6 +428,99 @@ impl IsoFieldData {
         let vec = data.to_vec();
         String::from_utf8(vec).map_or_else(|err| Self::Raw(err.into_bytes()), Self::String)
     }
+
+    /// Strict counterpart of [`IsoFieldData::from_bytes`], which fails if the data contains anything except printable ASCII characters.
+    pub fn from_ascii(data: Bytes) -> Result<Self, Error> {
+        if let Some(pos) = data.iter().position(|b| !matches!(b, 0x20..=0x7e)) {
+            return Err(Error::IncorrectData(format!(
+                "Non-printable ASCII byte 0x{:02X} at position {}",
+                data[pos], pos
+            )));
+        }
+        Ok(Self::Bytes(data))
+    }
+
+    /// Decodes the data as packed BCD number.
+    pub fn as_bcd_u64(&self) -> Result<u64, Error> {
+        let mut v: u64 = 0;
+        for b in self.as_bytes() {
+            let digits = decode_bcd_x2(*b)? as u64;
+            v = v
+                .checked_mul(100)
+                .and_then(|v| v.checked_add(digits))
+                .ok_or_else(|| Error::Bounds("BCD number doesn't fit in u64".into()))?;
+        }
+        Ok(v)
+    }
+
+    /// Decodes the data as signed number with COBOL overpunch in the last digit: `{` and `A`-`I` for positive
+    /// 0-9, `}` and `J`-`R` for negative 0-9. Plain last digit means positive number.
+    pub fn parse_overpunch(&self) -> Result<i64, Error> {
+        let data = self.as_bytes();
+        let (last, digits) = data
+            .split_last()
+            .ok_or_else(|| Error::IncorrectData("Overpunch number is empty".into()))?;
+        if let Some(pos) = digits.iter().position(|b| !b.is_ascii_digit()) {
+            return Err(Error::IncorrectData(format!(
+                "Overpunch number should contain digits, got byte 0x{:02X} at position {}",
+                digits[pos], pos
+            )));
+        }
+        let (last_digit, negative) = match last {
+            b'0'..=b'9' => (last - b'0', false),
+            b'{' => (0, false),
+            b'A'..=b'I' => (last - b'A' + 1, false),
+            b'}' => (0, true),
+            b'J'..=b'R' => (last - b'J' + 1, true),
+            _ => {
+                return Err(Error::IncorrectData(format!(
+                    "Unknown overpunch byte 0x{:02X}",
+                    last
+                )))
+            }
+        };
+
+        let overflow = || Error::Bounds("Overpunch number doesn't fit in i64".into());
+        let mut v: i64 = 0;
+        for d in digits.iter().map(|b| b - b'0').chain(Some(last_digit)) {
+            v = v
+                .checked_mul(10)
+                .and_then(|v| v.checked_add(i64::from(d)))
+                .ok_or_else(overflow)?;
+        }
+        Ok(if negative { -v } else { v })
+    }
+
+    /// Encodes `value` as packed BCD number of `digits` digits, left-padded with zeros.
+    ///
+    /// Odd `digits` count is padded with one more leading zero nibble. Zero `digits` give empty data for `value` 0,
+    /// which [`IsoFieldData::as_bcd_u64`] decodes back as 0, and an error for any other value.
+    pub fn from_bcd(value: u64, digits: usize) -> Result<Self, Error> {
+        let value_digits = match value {
+            0 => 0,
+            _ => value.to_string().len(),
+        };
+        if value_digits > digits {
+            return Err(Error::Bounds(format!(
+                "u64 '{}' contains more than {} digits",
+                value, digits
+            )));
+        }
+
+        let mut buf = vec![0u8; digits.div_ceil(2)];
+        let mut rest = value;
+        for b in buf.iter_mut().rev() {
+            *b = encode_bcd_x2((rest % 100) as u8)?;
+            rest /= 100;
+        }
+        Ok(Self::Raw(buf))
+    }
+}
+
+impl From<Bytes> for IsoFieldData {
+    fn from(v: Bytes) -> Self {
+        Self::Bytes(v)
+    }
 }
 
 impl From<String> for IsoFieldData {
@@ -131,17 +547,98 @@ impl From<&[u8]> for IsoFieldData {
     }
 }
 
+/// Data which is valid UTF-8 string is serialized as plain string, binary data is serialized as `{"raw": "<base64>"}`.
+impl Serialize for IsoFieldData {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use base64::Engine;
+        use serde::ser::SerializeMap;
+
+        let raw = match self {
+            Self::String(v) => return serializer.serialize_str(v),
+            Self::Raw(v) => v.as_slice(),
+            Self::Bytes(v) => match core::str::from_utf8(v) {
+                Ok(v) => return serializer.serialize_str(v),
+                Err(_) => v.as_ref(),
+            },
+        };
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(
+            "raw",
+            &base64::engine::general_purpose::STANDARD.encode(raw),
+        )?;
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for IsoFieldData {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use base64::Engine;
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            String(String),
+            Raw { raw: String },
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::String(v) => Ok(Self::String(v)),
+            Repr::Raw { raw } => base64::engine::general_purpose::STANDARD
+                .decode(raw)
+                .map(Self::Raw)
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Field data is compared by its bytes regardless of the variant.
+impl PartialEq for IsoFieldData {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for IsoFieldData {}
+
 impl<T: AsRef<[u8]> + ?Sized> PartialEq<T> for IsoFieldData {
     fn eq(&self, other: &T) -> bool {
         self.as_bytes() == other.as_ref()
     }
 }
 
+/// How [`SigmaRequest::redact`] treats the listed fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedactMode {
+    /// Replace each character of the data with `*`, counting characters same as [`SigmaRequest::mask_pan`].
+    Mask,
+    /// Remove the field.
+    Remove,
+}
+
+/// Difference of a single field between two requests, see [`SigmaRequest::diff`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FieldDiff {
+    Added {
+        tag: Tag,
+        value: IsoFieldData,
+    },
+    Removed {
+        tag: Tag,
+        value: IsoFieldData,
+    },
+    Changed {
+        tag: Tag,
+        old: IsoFieldData,
+        new: IsoFieldData,
+    },
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct SigmaRequest {
-    saf: String,
+    saf: Saf,
     source: String,
     mti: String,
+    /// Authorization serno. It is left public for advanced use, prefer [`SigmaRequest::set_auth_serno`] which rejects values longer than 10 digits.
     pub auth_serno: u64,
     pub tags: BTreeMap<u16, String>,
     pub iso_fields: BTreeMap<u16, IsoFieldData>,
@@ -150,11 +647,11 @@ pub struct SigmaRequest {
 
 impl SigmaRequest {
     pub fn new(saf: &str, source: &str, mti: &str, auth_serno: u64) -> Result<Self, Error> {
-        validate_saf(saf)?;
+        let saf = saf.parse()?;
         validate_source(source)?;
         validate_mti(mti)?;
         Ok(Self {
-            saf: saf.into(),
+            saf,
             source: source.into(),
             mti: mti.into(),
             auth_serno,
@@ -164,7 +661,57 @@ impl SigmaRequest {
         })
     }
 
-    pub fn from_json_value(mut data: Value) -> Result<SigmaRequest, Error> {
+    /// Reports all keys of JSON representation of the request which are neither header keys (`SAF`, `SRC`, `MTI`,
+    /// `Serno`) nor valid tags, without building the request.
+    pub fn validate_json_keys(value: &Value) -> Vec<(String, Error)> {
+        let map = match value.as_object() {
+            Some(map) => map,
+            None => {
+                return vec![(
+                    String::new(),
+                    Error::IncorrectData("SigmaRequest JSON should be object".into()),
+                )]
+            }
+        };
+        map.keys()
+            .filter(|k| !JSON_HEADER_KEYS.contains(&k.as_str()))
+            .filter_map(|k| Tag::from_str(k).err().map(|e| (k.clone(), e)))
+            .collect()
+    }
+
+    /// Builds request from its JSON representation.
+    ///
+    /// Missing `Serno` is generated with [`gen_random_auth_serno`] if `std` feature is enabled, otherwise it is an error.
+    pub fn from_json_value(data: Value) -> Result<SigmaRequest, Error> {
+        Self::from_json_value_with(data, JsonOptions::default())
+    }
+
+    /// Same as [`SigmaRequest::from_json_value`], but with non-default [`JsonOptions`].
+    pub fn from_json_value_with(data: Value, options: JsonOptions) -> Result<SigmaRequest, Error> {
+        Self::from_json_value_impl(data, &options, || {
+            #[cfg(feature = "std")]
+            return Some(gen_random_auth_serno());
+            #[cfg(not(feature = "std"))]
+            return None;
+        })
+    }
+
+    /// Same as [`SigmaRequest::from_json_value_with`], but missing `Serno` is generated using provided random number
+    /// generator.
+    #[cfg(feature = "std")]
+    pub fn from_json_value_with_rng<R: rand::Rng + ?Sized>(
+        data: Value,
+        options: JsonOptions,
+        rng: &mut R,
+    ) -> Result<SigmaRequest, Error> {
+        Self::from_json_value_impl(data, &options, || Some(gen_random_auth_serno_with(rng)))
+    }
+
+    fn from_json_value_impl(
+        mut data: Value,
+        options: &JsonOptions,
+        gen_auth_serno: impl FnOnce() -> Option<u64>,
+    ) -> Result<SigmaRequest, Error> {
         let data = data
             .as_object_mut()
             .ok_or_else(|| Error::IncorrectData("SigmaRequest JSON should be object".into()))?;
@@ -178,10 +725,9 @@ impl SigmaRequest {
                             req.$fname(v.to_string())?;
                         }
                         None => {
-                            return Err(Error::IncorrectFieldData {
-                                field_name: $pname.to_string(),
-                                should_be: $comment.to_string(),
-                            });
+                            return Err(
+                                Error::incorrect_field_data($pname, $comment).got(x.to_string())
+                            );
                         }
                     },
                     None => {
@@ -197,37 +743,39 @@ impl SigmaRequest {
         // Authorization serno
         match data.remove("Serno") {
             Some(x) => {
-                if let Some(s) = x.as_str() {
-                    req.auth_serno = s.parse::<u64>().map_err(|_| Error::IncorrectFieldData {
-                        field_name: "Serno".into(),
-                        should_be: "integer".into(),
-                    })?;
-                } else if let Some(v) = x.as_u64() {
-                    req.auth_serno = v;
-                } else {
-                    return Err(Error::IncorrectFieldData {
-                        field_name: "Serno".into(),
-                        should_be: "u64 or String with integer".into(),
-                    });
-                }
+                req.auth_serno = json_u64("Serno", &x)?;
             }
             None => {
-                req.auth_serno = util::gen_random_auth_serno();
+                req.auth_serno =
+                    gen_auth_serno().ok_or_else(|| Error::MissingField("Serno".into()))?;
             }
         }
 
         for (name, field_data) in data.iter() {
-            let tag = Tag::from_str(name)?;
+            let tag = match Tag::from_str(name) {
+                Ok(tag) => tag,
+                Err(_) if options.ignore_unknown => continue,
+                Err(e) => return Err(e),
+            };
             let content = if let Some(x) = field_data.as_str() {
                 x.into()
             } else if let Some(x) = field_data.as_u64() {
                 format!("{}", x)
             } else {
-                return Err(Error::IncorrectFieldData {
-                    field_name: name.clone(),
-                    should_be: "u64 or String with integer".into(),
-                });
+                return Err(
+                    Error::incorrect_field_data(name, "u64 or String with integer")
+                        .got(field_data.to_string()),
+                );
             };
+            if let Some(max_len) = options.max_lengths.get(&tag) {
+                if content.chars().count() > *max_len {
+                    return Err(Error::incorrect_field_data(
+                        name,
+                        &format!("at most {} characters", max_len),
+                    )
+                    .got(&content));
+                }
+            }
             match tag {
                 Tag::Regular(i) => {
                     req.tags.insert(i, content);
@@ -244,425 +792,1485 @@ impl SigmaRequest {
         Ok(req)
     }
 
-    pub fn encode(&self) -> Result<Bytes, Error> {
-        let mut buf = BytesMut::with_capacity(8192);
-        buf.extend_from_slice(b"00000");
-
-        buf.extend_from_slice(self.saf.as_bytes());
-        buf.extend_from_slice(self.source.as_bytes());
-        buf.extend_from_slice(self.mti.as_bytes());
-        if self.auth_serno > 9999999999 {
-            buf.extend_from_slice(&format!("{}", self.auth_serno).as_bytes()[0..10]);
-        } else {
-            buf.extend_from_slice(format!("{:010}", self.auth_serno).as_bytes());
-        }
+    /// Builds JSON representation of the request, which is accepted back by [`SigmaRequest::from_json_value`].
+    pub fn to_json_value(&self) -> Value {
+        let mut map = serde_json::Map::new();
+        map.insert("SAF".into(), Value::String(self.saf.to_string()));
+        map.insert("SRC".into(), Value::String(self.source.clone()));
+        map.insert("MTI".into(), Value::String(self.mti.clone()));
+        map.insert("Serno".into(), Value::from(self.auth_serno));
 
         for (k, v) in self.tags.iter() {
-            encode_field_to_buf(Tag::Regular(*k), v.as_bytes(), &mut buf)?;
+            map.insert(Tag::Regular(*k).canonical_key(), Value::String(v.clone()));
         }
 
         for (k, v) in self.iso_fields.iter() {
-            encode_field_to_buf(Tag::Iso(*k), v.as_bytes(), &mut buf)?;
+            map.insert(
+                Tag::Iso(*k).canonical_key(),
+                Value::String(v.to_cow_str_lossy().into_owned()),
+            );
         }
 
         for ((k, k1), v) in self.iso_subfields.iter() {
-            encode_field_to_buf(Tag::IsoSubfield(*k, *k1), v.as_bytes(), &mut buf)?;
+            map.insert(
+                Tag::IsoSubfield(*k, *k1).canonical_key(),
+                Value::String(v.to_cow_str_lossy().into_owned()),
+            );
         }
 
-        let msg_len = buf.len() - 5;
-        buf[0..5].copy_from_slice(format!("{:05}", msg_len).as_bytes());
-        Ok(buf.freeze())
+        Value::Object(map)
     }
 
-    pub fn decode(mut data: Bytes) -> Result<Self, Error> {
-        let mut req = Self::new("N", "X", "0100", 0)?;
-
-        let msg_len = parse_ascii_bytes_lossy!(
-            &bytes_split_to(&mut data, 5)?,
-            usize,
-            Error::incorrect_field_data("message length", "valid integer")
-        )?;
-        let mut data = bytes_split_to(&mut data, msg_len)?;
+    /// Encodes the request. Fails with [`Error::Bounds`] if `auth_serno` doesn't fit in 10 digits.
+    pub fn encode(&self) -> Result<Bytes, Error> {
+        self.encode_impl(false, LENGTH_BYTES_COUNT)
+    }
 
-        req.set_saf(String::from_utf8_lossy(&bytes_split_to(&mut data, 1)?).to_string())?;
-        req.set_source(String::from_utf8_lossy(&bytes_split_to(&mut data, 1)?).to_string())?;
-        req.set_mti(String::from_utf8_lossy(&bytes_split_to(&mut data, 4)?).to_string())?;
-        req.auth_serno = String::from_utf8_lossy(&bytes_split_to(&mut data, 10)?)
-            .trim()
-            .parse::<u64>()
-            .map_err(|_| Error::IncorrectFieldData {
-                field_name: "Serno".into(),
-                should_be: "u64".into(),
-            })?;
+    /// Encodes the request, keeping only first 10 digits of `auth_serno` if it is longer.
+    pub fn encode_truncating_auth_serno(&self) -> Result<Bytes, Error> {
+        self.encode_impl(true, LENGTH_BYTES_COUNT)
+    }
 
-        while !data.is_empty() {
-            let (tag, data_src) = decode_field_from_cursor(&mut data)?;
+    /// Encodes the request with length prefix of `len_width` digits instead of [`LENGTH_BYTES_COUNT`].
+    pub fn encode_with_len_width(&self, len_width: usize) -> Result<Bytes, Error> {
+        self.encode_impl(false, len_width)
+    }
 
-            match tag {
-                Tag::Regular(i) => {
-                    req.tags
-                        .insert(i, String::from_utf8_lossy(&data_src).into_owned());
-                }
-                Tag::Iso(i) => {
-                    req.iso_fields.insert(i, IsoFieldData::from_bytes(data_src));
-                }
-                Tag::IsoSubfield(i, si) => {
-                    req.iso_subfields
-                        .insert((i, si), IsoFieldData::from_bytes(data_src));
-                }
-            }
-        }
+    /// Encodes the request appending it to `dst`, which is left intact on failure.
+    pub fn encode_to(&self, dst: &mut BytesMut) -> Result<(), Error> {
+        self.encode_to_impl(dst, false, LENGTH_BYTES_COUNT)
+    }
 
-        Ok(req)
+    /// Encodes the request into an owned `Vec`, e.g. to hand it over an FFI boundary.
+    pub fn encode_vec(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.encode()?.to_vec())
     }
 
-    pub fn saf(&self) -> &str {
-        &self.saf
+    /// Exact length of the encoded request including the length prefix, e.g. to size buffers upfront.
+    pub fn encoded_len(&self) -> usize {
+        LENGTH_BYTES_COUNT
+            + REQUEST_HEADER_LEN
+            + self
+                .fields()
+                .map(|(_, d)| FIELD_HEADER_LEN + d.len())
+                .sum::<usize>()
     }
 
-    pub fn set_saf(&mut self, v: String) -> Result<(), Error> {
-        validate_saf(&v)?;
-        self.saf = v;
-        Ok(())
+    /// Encodes all `reqs` into single buffer of concatenated messages, which is accepted by [`SigmaRequest::decode_all`].
+    pub fn encode_batch(reqs: &[SigmaRequest]) -> Result<Bytes, Error> {
+        let mut buf = BytesMut::with_capacity(reqs.iter().map(Self::encoded_len).sum());
+        for req in reqs {
+            req.encode_to(&mut buf)?;
+        }
+        Ok(buf.freeze())
     }
 
-    pub fn source(&self) -> &str {
-        &self.source
+    fn encode_impl(&self, truncate_auth_serno: bool, len_width: usize) -> Result<Bytes, Error> {
+        let mut buf = BytesMut::with_capacity(self.encoded_len() - LENGTH_BYTES_COUNT + len_width);
+        self.encode_to_impl(&mut buf, truncate_auth_serno, len_width)?;
+        Ok(buf.freeze())
     }
 
-    pub fn set_source(&mut self, v: String) -> Result<(), Error> {
-        validate_source(&v)?;
-        self.source = v;
-        Ok(())
+    fn encode_to_impl(
+        &self,
+        dst: &mut BytesMut,
+        truncate_auth_serno: bool,
+        len_width: usize,
+    ) -> Result<(), Error> {
+        self.encode_fields_to(dst, truncate_auth_serno, len_width, self.fields())
     }
 
-    pub fn mti(&self) -> &str {
-        &self.mti
+    /// Encodes the request header followed by `fields` in the given order.
+    fn encode_fields_to<'a>(
+        &self,
+        dst: &mut BytesMut,
+        truncate_auth_serno: bool,
+        len_width: usize,
+        fields: impl Iterator<Item = (Tag, &'a [u8])>,
+    ) -> Result<(), Error> {
+        encode_message_to_buf(dst, len_width, |buf| {
+            self.encode_header_to(buf, truncate_auth_serno)?;
+
+            for (tag, data) in fields {
+                encode_field_to_buf(tag, data, buf)?;
+            }
+            Ok(())
+        })
     }
 
-    pub fn set_mti(&mut self, v: String) -> Result<(), Error> {
-        validate_mti(&v)?;
-        self.mti = v;
-        Ok(())
+    /// Encodes the request header: SAF, SRC, MTI and `auth_serno`.
+    fn encode_header_to(&self, buf: &mut BytesMut, truncate_auth_serno: bool) -> Result<(), Error> {
+        buf.extend_from_slice(self.saf.as_str().as_bytes());
+        buf.extend_from_slice(self.source.as_bytes());
+        buf.extend_from_slice(self.mti.as_bytes());
+        encode_auth_serno_to_buf(self.auth_serno, truncate_auth_serno, buf)
     }
-}
 
-#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
-pub struct FeeData {
-    pub reason: u16,
-    pub currency: u16,
-    pub amount: u64,
-}
+    /// Encodes the request lazily as chunks: the length prefix with the header, followed by one chunk per field in
+    /// the order of [`SigmaRequest::fields`], e.g. to write them to a socket without building the whole message.
+    ///
+    /// The length prefix comes from [`SigmaRequest::encoded_len`] and all the field headers are checked before the
+    /// first chunk, so encoding errors are returned upfront and concatenated chunks are the same as
+    /// [`SigmaRequest::encode`]. Field data is copied into its chunk only when the chunk is requested.
+    pub fn encode_stream(&self) -> Result<impl Iterator<Item = Bytes> + '_, Error> {
+        let mut header = BytesMut::with_capacity(LENGTH_BYTES_COUNT + REQUEST_HEADER_LEN);
+        header.extend_from_slice(&format_length_prefix(
+            self.encoded_len() - LENGTH_BYTES_COUNT,
+        )?);
+        self.encode_header_to(&mut header, false)?;
+
+        let field_headers = self
+            .fields()
+            .map(|(tag, data)| encode_field_header(&tag, data.len()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let fields = self
+            .fields()
+            .zip(field_headers)
+            .map(|((_, data), field_header)| {
+                let mut buf = BytesMut::with_capacity(FIELD_HEADER_LEN + data.len());
+                buf.extend_from_slice(&field_header);
+                buf.extend_from_slice(data);
+                buf.freeze()
+            });
+        Ok(core::iter::once(header.freeze()).chain(fields))
+    }
 
-impl FeeData {
-    pub fn from_slice(data: &[u8]) -> Result<Self, Error> {
-        if data.len() >= 8 {
-            // "\x00\x32\x00\x00\x108116978300"
-            let reason = parse_ascii_bytes_lossy!(
-                &data[0..4],
-                u16,
-                Error::incorrect_field_data("FeeData.reason", "valid integer")
-            )?;
-            let currency = parse_ascii_bytes_lossy!(
-                &data[4..7],
-                u16,
-                Error::incorrect_field_data("FeeData.currency", "valid integer")
-            )?;
-            let amount = parse_ascii_bytes_lossy!(
-                &data[7..],
-                u64,
-                Error::incorrect_field_data("FeeData.amount", "valid integer")
-            )?;
-            Ok(Self {
-                reason,
-                currency,
-                amount,
-            })
-        } else {
-            Err(Error::IncorrectData(
-                "FeeData slice should be longer than 8 bytes".into(),
-            ))
-        }
+    /// Encodes the request emitting fields listed in `order` first, in that order, and then the rest of the fields in
+    /// the default order, see [`SigmaRequest::fields`]. Listed fields which are absent are skipped.
+    pub fn encode_ordered(&self, order: &[Tag]) -> Result<Bytes, Error> {
+        self.encode_ordered_impl(order, false)
     }
 
-    pub fn encode(&self) -> Result<Bytes, Error> {
-        let mut buf = BytesMut::new();
+    /// Same as [`SigmaRequest::encode_ordered`], but fails with [`Error::MissingField`] if a listed field is absent.
+    pub fn encode_ordered_strict(&self, order: &[Tag]) -> Result<Bytes, Error> {
+        self.encode_ordered_impl(order, true)
+    }
 
-        if self.reason > 9999 {
-            return Err(Error::Bounds(
-                "FeeData.reason should be less or equal 9999".into(),
-            ));
+    fn encode_ordered_impl(&self, order: &[Tag], strict: bool) -> Result<Bytes, Error> {
+        let mut listed = BTreeSet::new();
+        let mut fields = Vec::with_capacity(order.len());
+        for tag in order {
+            let data = match self.field_data(tag) {
+                Some(data) => data,
+                None if strict => return Err(Error::MissingField(tag.to_string())),
+                None => continue,
+            };
+            if listed.insert(tag) {
+                fields.push((tag.clone(), data));
+            }
         }
-        buf.extend_from_slice(format!("{:<04}", self.reason).as_bytes());
+        let rest = self.fields().filter(|(tag, _)| !listed.contains(tag));
 
-        if self.currency > 999 {
-            return Err(Error::Bounds(
-                "FeeData.reason should be less or equal 999".into(),
-            ));
-        }
-        buf.extend_from_slice(format!("{:<03}", self.currency).as_bytes());
+        let mut buf = BytesMut::with_capacity(self.encoded_len());
+        self.encode_fields_to(
+            &mut buf,
+            false,
+            LENGTH_BYTES_COUNT,
+            fields.into_iter().chain(rest),
+        )?;
+        Ok(buf.freeze())
+    }
 
-        buf.extend_from_slice(format!("{}", self.amount).as_bytes());
+    /// Decodes all requests from buffer containing concatenated length-prefixed messages.
+    pub fn decode_all(data: Bytes) -> Result<Vec<Self>, Error> {
+        decode_all(data, Self::decode)
+    }
 
-        Ok(buf.freeze())
+    /// Decodes the first message of `data`, returning it along with the bytes following it.
+    pub fn decode_one(data: Bytes) -> Result<(Self, Bytes), Error> {
+        decode_one(data, Self::decode)
     }
-}
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
-pub struct SigmaResponse {
-    mti: String,
-    pub auth_serno: u64,
-    pub reason: u32,
-    #[serde(default, skip_serializing_if = "Vec::is_empty")]
-    pub fees: Vec<FeeData>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub adata: Option<String>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub supdata: Option<String>,
-    #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub xri: Option<String>,
-}
+    /// Reads and decodes single message from blocking reader `r`.
+    #[cfg(feature = "std")]
+    pub fn read_from_sync<R: std::io::Read + ?Sized>(r: &mut R) -> Result<Self, Error> {
+        Self::decode(read_message(r)?)
+    }
 
-impl SigmaResponse {
-    pub fn new(mti: &str, auth_serno: u64, reason: u32) -> Result<Self, Error> {
-        validate_mti(mti)?;
-        Ok(Self {
-            mti: mti.into(),
-            auth_serno,
-            reason,
-            fees: Vec::new(),
-            adata: None,
-            supdata: None,
-            xri: None,
-        })
+    pub fn decode(data: Bytes) -> Result<Self, Error> {
+        Self::decode_with_options(data, DecodeOptions::default())
     }
 
-    pub fn decode(mut data: Bytes) -> Result<Self, Error> {
-        let mut resp = Self::new("0100", 0, 0)?;
+    /// Decodes the request from a borrowed slice, copying it, e.g. when it comes over an FFI boundary.
+    pub fn decode_slice(data: &[u8]) -> Result<Self, Error> {
+        Self::decode(Bytes::copy_from_slice(data))
+    }
 
-        let msg_len = parse_ascii_bytes_lossy!(
-            &bytes_split_to(&mut data, 5)?,
-            usize,
-            Error::incorrect_field_data("message length", "valid integer")
-        )?;
-        let mut data = bytes_split_to(&mut data, msg_len)?;
+    /// Decodes message with length prefix of `len_width` digits instead of [`LENGTH_BYTES_COUNT`].
+    pub fn decode_with_len_width(data: Bytes, len_width: usize) -> Result<Self, Error> {
+        Self::decode_with_options(
+            data,
+            DecodeOptions {
+                len_width,
+                ..Default::default()
+            },
+        )
+    }
 
-        resp.set_mti(String::from_utf8_lossy(&bytes_split_to(&mut data, 4)?).to_string())?;
-        resp.auth_serno = String::from_utf8_lossy(&bytes_split_to(&mut data, 10)?)
-            .trim()
-            .parse::<u64>()
-            .map_err(|_| Error::IncorrectFieldData {
-                field_name: "Serno".into(),
-                should_be: "u64".into(),
-            })?;
+    pub fn decode_with_options(data: Bytes, options: DecodeOptions) -> Result<Self, Error> {
+        Self::decode_impl(data, options, |_, _| {})
+    }
 
-        while !data.is_empty() {
-            /*
-             *  |
-             *  |  T  | \x00 | \x31 | \x00 | \x00 | \x04 |  8  |  1  |  0  |  0  |
-             *        |             |      |             |                       |
-             *        |__ tag id ___|      |tag data len |_______ data __________|
-             */
-            let (tag, data_src) = decode_field_from_cursor(&mut data)?;
+    /// Same as [`SigmaRequest::decode`], but calls `f` with tag and data of each field as it is decoded, e.g. to collect metrics.
+    pub fn decode_with<F: FnMut(&Tag, &[u8])>(data: Bytes, f: F) -> Result<Self, Error> {
+        Self::decode_impl(data, DecodeOptions::default(), f)
+    }
 
-            match tag {
-                Tag::Regular(31) => {
-                    resp.reason = parse_ascii_bytes_lossy!(
-                        &data_src,
-                        u32,
-                        Error::incorrect_field_data("reason", "shloud be u32")
-                    )?;
+    fn decode_impl(
+        mut data: Bytes,
+        options: DecodeOptions,
+        mut f: impl FnMut(&Tag, &[u8]),
+    ) -> Result<Self, Error> {
+        let mut req = Self::new("N", "X", "0100", 0)?;
+
+        let mut data = options.split_message_body(&mut data)?;
+        let msg_len = data.len();
+
+        req.set_saf(String::from_utf8_lossy(&bytes_split_to(&mut data, 1)?).to_string())?;
+        req.set_source(String::from_utf8_lossy(&bytes_split_to(&mut data, 1)?).to_string())?;
+        req.set_mti(String::from_utf8_lossy(&bytes_split_to(&mut data, MTI_LEN)?).to_string())?;
+        req.auth_serno = decode_auth_serno(&bytes_split_to(&mut data, SERNO_LEN)?)?;
+
+        while !data.is_empty() && !options.is_padding(&data) {
+            let offset = msg_len - data.len();
+            let (tag, data_src) = decode_field_from_cursor_at(&mut data, offset)?;
+            f(&tag, &data_src);
+
+            let duplicate = match tag {
+                Tag::Regular(i) => req
+                    .tags
+                    .insert(i, String::from_utf8_lossy(&data_src).into_owned())
+                    .is_some(),
+                Tag::Iso(i) => req
+                    .iso_fields
+                    .insert(i, options.field_data(data_src))
+                    .is_some(),
+                Tag::IsoSubfield(i, si) => req
+                    .iso_subfields
+                    .insert((i, si), options.field_data(data_src))
+                    .is_some(),
+            };
+            options.check_duplicate(&tag, duplicate, offset)?;
+        }
+
+        Ok(req)
+    }
+
+    /// Iterates over all fields in the order they are encoded: regular tags, then ISO fields, then ISO subfields.
+    pub fn fields(&self) -> impl Iterator<Item = (Tag, &[u8])> {
+        let tags = self
+            .tags
+            .iter()
+            .map(|(k, v)| (Tag::Regular(*k), v.as_bytes()));
+        let iso_fields = self
+            .iso_fields
+            .iter()
+            .map(|(k, v)| (Tag::Iso(*k), v.as_bytes()));
+        let iso_subfields = self
+            .iso_subfields
+            .iter()
+            .map(|((k, k1), v)| (Tag::IsoSubfield(*k, *k1), v.as_bytes()));
+        tags.chain(iso_fields).chain(iso_subfields)
+    }
+
+    /// Data of the field `tag` if it is present.
+    pub fn field_data(&self, tag: &Tag) -> Option<&[u8]> {
+        match tag {
+            Tag::Regular(n) => self.tags.get(n).map(String::as_bytes),
+            Tag::Iso(n) => self.iso_fields.get(n).map(IsoFieldData::as_bytes),
+            Tag::IsoSubfield(n, sn) => self
+                .iso_subfields
+                .get(&(*n, *sn))
+                .map(IsoFieldData::as_bytes),
+        }
+    }
+
+    /// Checks invariants of the request which are not enforced on construction:
+    /// inner MTI (ISO field 0) is 4 digits if present and `auth_serno` fits in 10 digits.
+    ///
+    /// All violations are reported in single [`Error::IncorrectData`].
+    pub fn validate_consistency(&self) -> Result<(), Error> {
+        let mut violations = Vec::new();
+
+        if let Some(inner_mti) = self.iso_fields.get(&0) {
+            if validate_mti(&inner_mti.to_cow_str_lossy()).is_err() {
+                violations.push("i000 should be 4 digit number (string)".to_string());
+            }
+        }
+        if self.auth_serno > MAX_AUTH_SERNO {
+            violations.push(format!(
+                "Serno '{}' contains more than 10 digits",
+                self.auth_serno
+            ));
+        }
+
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(Error::IncorrectData(violations.join("; "))),
+        }
+    }
+
+    /// Masks PAN (ISO field 2) in place, keeping only first 6 and last 4 characters, e.g. `555544******1111`.
+    ///
+    /// Does nothing if the field is absent or is not longer than 10 characters. Data is masked by characters, so
+    /// multi-byte UTF-8 characters are never split, and invalid UTF-8 is converted lossily first.
+    pub fn mask_pan(&mut self) {
+        if let Some(pan) = self.iso_fields.get_mut(&2) {
+            let chars = pan.to_cow_str_lossy().chars().collect::<Vec<_>>();
+            if chars.len() <= 10 {
+                return;
+            }
+            let (head, tail) = (&chars[..6], &chars[chars.len() - 4..]);
+            let mut masked: String = head.iter().collect();
+            masked.extend(chars[6..chars.len() - 4].iter().map(|_| '*'));
+            masked.extend(tail);
+            *pan = IsoFieldData::String(masked);
+        }
+    }
+
+    /// Masks or removes the fields listed in `tags`, e.g. before logging. Absent fields are skipped.
+    pub fn redact(&mut self, tags: &[Tag], mode: RedactMode) {
+        for tag in tags {
+            match (tag, mode) {
+                (Tag::Regular(n), RedactMode::Remove) => {
+                    self.tags.remove(n);
                 }
-                Tag::Regular(32) => {
-                    resp.fees.push(FeeData::from_slice(&data_src)?);
+                (Tag::Regular(n), RedactMode::Mask) => {
+                    if let Some(v) = self.tags.get_mut(n) {
+                        *v = "*".repeat(v.chars().count());
+                    }
                 }
-                Tag::Regular(33) => resp.xri = Some(String::from_utf8_lossy(&data_src).to_string()),
-                Tag::Regular(48) => {
-                    resp.adata = Some(String::from_utf8_lossy(&data_src).to_string());
+                (Tag::Iso(n), RedactMode::Remove) => {
+                    self.iso_fields.remove(n);
                 }
-                Tag::Regular(50) => {
-                    resp.supdata = Some(String::from_utf8_lossy(&data_src).to_string());
+                (Tag::Iso(n), RedactMode::Mask) => {
+                    if let Some(v) = self.iso_fields.get_mut(n) {
+                        *v = IsoFieldData::String("*".repeat(v.to_cow_str_lossy().chars().count()));
+                    }
+                }
+                (Tag::IsoSubfield(n, sn), RedactMode::Remove) => {
+                    self.iso_subfields.remove(&(*n, *sn));
+                }
+                (Tag::IsoSubfield(n, sn), RedactMode::Mask) => {
+                    if let Some(v) = self.iso_subfields.get_mut(&(*n, *sn)) {
+                        *v = IsoFieldData::String("*".repeat(v.to_cow_str_lossy().chars().count()));
+                    }
                 }
-                _ => {}
             }
         }
+    }
 
-        Ok(resp)
+    /// Returns copy of the request with masked PAN, see [`SigmaRequest::mask_pan`].
+    pub fn masked(&self) -> SigmaRequest {
+        let mut req = self.clone();
+        req.mask_pan();
+        req
+    }
+
+    /// Lists fields which are added, removed or changed in `other` compared to `self`.
+    ///
+    /// Differences are ordered by tag: regular tags, then ISO fields, then ISO subfields.
+    /// Header (SAF, SRC, MTI and serno) is not compared.
+    pub fn diff(&self, other: &SigmaRequest) -> Vec<FieldDiff> {
+        let old: BTreeMap<Tag, &[u8]> = self.fields().collect();
+        let new: BTreeMap<Tag, &[u8]> = other.fields().collect();
+        let tags: BTreeSet<&Tag> = old.keys().chain(new.keys()).collect();
+        let data = |v: &[u8]| IsoFieldData::from_bytes(Bytes::copy_from_slice(v));
+
+        tags.into_iter()
+            .filter_map(|tag| match (old.get(tag), new.get(tag)) {
+                (Some(o), Some(n)) if o == n => None,
+                (Some(o), Some(n)) => Some(FieldDiff::Changed {
+                    tag: tag.clone(),
+                    old: data(o),
+                    new: data(n),
+                }),
+                (Some(o), None) => Some(FieldDiff::Removed {
+                    tag: tag.clone(),
+                    value: data(o),
+                }),
+                (None, Some(n)) => Some(FieldDiff::Added {
+                    tag: tag.clone(),
+                    value: data(n),
+                }),
+                (None, None) => None,
+            })
+            .collect()
+    }
+
+    pub fn saf(&self) -> Saf {
+        self.saf
+    }
+
+    /// Sets SAF flag from its wire representation, `Y` or `N`.
+    pub fn set_saf(&mut self, v: String) -> Result<(), Error> {
+        self.saf = v.parse()?;
+        Ok(())
+    }
+
+    /// Whether the request is sent in store-and-forward mode, i.e. SAF is `Y`.
+    pub fn is_saf(&self) -> bool {
+        self.saf.into()
+    }
+
+    /// Clears SAF flag, e.g. before replaying the request which is already forwarded.
+    pub fn mark_forwarded(&mut self) {
+        self.saf = Saf::No;
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn set_source(&mut self, v: String) -> Result<(), Error> {
+        validate_source(&v)?;
+        self.source = v;
+        Ok(())
+    }
+
+    /// Same as [`SigmaRequest::set_source`], but additionally requires source to be one of `allowed` chars.
+    pub fn set_source_checked(&mut self, v: &str, allowed: &[char]) -> Result<(), Error> {
+        validate_source(v)?;
+        if !v.chars().all(|c| allowed.contains(&c)) {
+            let allowed: Vec<String> = allowed.iter().map(char::to_string).collect();
+            return Err(Error::incorrect_field_data(
+                "SRC",
+                &format!("one of {}", allowed.join(", ")),
+            )
+            .got(v));
+        }
+        self.source = v.into();
+        Ok(())
     }
 
     pub fn mti(&self) -> &str {
         &self.mti
     }
 
+    pub fn mti_parsed(&self) -> Mti {
+        self.mti.parse().expect("MTI is validated on assignment")
+    }
+
     pub fn set_mti(&mut self, v: String) -> Result<(), Error> {
         validate_mti(&v)?;
         self.mti = v;
         Ok(())
     }
 
-    pub fn encode(&self) -> Result<Bytes, Error> {
-        let mut buf = BytesMut::with_capacity(8192);
-        buf.extend_from_slice(b"00000");
+    /// Inner message type, i.e. ISO field 0, if it is present and valid UTF-8.
+    pub fn inner_mti(&self) -> Option<&str> {
+        self.iso_fields
+            .get(&0)
+            .and_then(|v| core::str::from_utf8(v.as_bytes()).ok())
+    }
 
-        buf.extend_from_slice(self.mti.as_bytes());
-        if self.auth_serno > 9999999999 {
-            buf.extend_from_slice(&format!("{}", self.auth_serno).as_bytes()[0..10]);
-        } else {
-            buf.extend_from_slice(format!("{:010}", self.auth_serno).as_bytes());
-        }
-        encode_field_to_buf(
-            Tag::Regular(31),
-            format!("{}", self.reason).as_bytes(),
-            &mut buf,
-        )?;
-        for i in &self.fees {
-            encode_field_to_buf(Tag::Regular(32), &i.encode()?, &mut buf)?;
-        }
-        if let Some(ref adata) = self.adata {
-            encode_field_to_buf(Tag::Regular(48), adata.as_bytes(), &mut buf)?;
-        }
-        if let Some(ref xri) = self.xri {
-            encode_field_to_buf(Tag::Regular(33), xri.as_bytes(), &mut buf)?;
-        }
+    /// Sets inner message type, i.e. ISO field 0, which should be 4 digits same as MTI.
+    pub fn set_inner_mti(&mut self, v: &str) -> Result<(), Error> {
+        validate_mti(v)
+            .map_err(|_| Error::incorrect_field_data("i000", "4 digit number (string)").got(v))?;
+        self.iso_fields.insert(0, v.into());
+        Ok(())
+    }
 
-        let msg_len = buf.len() - 5;
-        buf[0..5].copy_from_slice(format!("{:05}", msg_len).as_bytes());
-        Ok(buf.freeze())
+    /// Sets authorization serno, failing with [`Error::Bounds`] if it is above [`MAX_AUTH_SERNO`].
+    pub fn set_auth_serno(&mut self, v: u64) -> Result<(), Error> {
+        validate_auth_serno(v)?;
+        self.auth_serno = v;
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    pub fn tag(&self, n: u16) -> Option<&str> {
+        self.tags.get(&n).map(String::as_str)
+    }
 
-    #[test]
-    fn ok() {
-        let payload = r#"{
-            "SAF": "Y",
-            "SRC": "M",
-            "MTI": "0200",
-            "Serno": 6007040979,
-            "T0000": 2371492071643,
-            "T0001": "C",
-            "T0002": 643,
-            "T0003": "000100000000",
-            "T0004": 978,
-            "T0005": "000300000000",
-            "T0006": "OPS6",
-            "T0007": 19,
-            "T0008": 643,
-            "T0009": 3102,
-            "T0010": 3104,
-            "T0011": 2,
-            "T0014": "IDDQD Bank",
-            "T0016": 74707182,
-            "T0018": "Y",
-            "T0022": "000000000010",
-            "i000": "0100",
-            "i002": "555544******1111",
-            "i003": "500000",
-            "i004": "000100000000",
-            "i006": "000100000000",
-            "i007": "0629151748",
-            "i011": "100250",
-            "i012": "181748",
-            "i013": "0629",
-            "i018": "0000",
-            "i022": "0000",
-            "i025": "02",
-            "i032": "010455",
-            "i037": "002595100250",
-            "i041": 990,
-            "i042": "DCZ1",
-            "i043": "IDDQD Bank.                         GE",
-            "i048": "USRDT|2595100250",
-            "i049": 643,
-            "i051": 643,
-            "i060": 3,
-            "i101": 91926242,
-            "i102": 2371492071643
-        }"#;
+    pub fn set_tag(&mut self, n: u16, v: impl Into<String>) {
+        self.tags.insert(n, v.into());
+    }
 
-        let r: SigmaRequest =
-            SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
-        assert_eq!(r.saf, "Y");
-        assert_eq!(r.source, "M");
-        assert_eq!(r.mti, "0200");
-        assert_eq!(r.auth_serno, 6007040979);
-        assert_eq!(r.tags.get(&0).unwrap(), "2371492071643");
-        assert_eq!(r.tags.get(&1).unwrap(), "C");
-        assert_eq!(r.tags.get(&2).unwrap(), "643");
-        assert_eq!(r.tags.get(&3).unwrap(), "000100000000");
-        assert_eq!(r.tags.get(&4).unwrap(), "978");
-        assert_eq!(r.tags.get(&5).unwrap(), "000300000000");
-        assert_eq!(r.tags.get(&6).unwrap(), "OPS6");
-        assert_eq!(r.tags.get(&7).unwrap(), "19");
-        assert_eq!(r.tags.get(&8).unwrap(), "643");
-        assert_eq!(r.tags.get(&9).unwrap(), "3102");
-        assert_eq!(r.tags.get(&10).unwrap(), "3104");
-        assert_eq!(r.tags.get(&11).unwrap(), "2");
+    pub fn remove_tag(&mut self, n: u16) -> Option<String> {
+        self.tags.remove(&n)
+    }
 
-        if r.tags.get(&12).is_some() {
-            unreachable!();
-        }
+    pub fn has_tag(&self, n: u16) -> bool {
+        self.tags.contains_key(&n)
+    }
 
-        if r.tags.get(&13).is_some() {
-            unreachable!();
-        }
+    /// Numbers of present regular tags in ascending order.
+    pub fn present_tags(&self) -> Vec<u16> {
+        self.tags.keys().copied().collect()
+    }
 
-        assert_eq!(r.tags.get(&14).unwrap(), "IDDQD Bank");
+    pub fn iso_field(&self, n: u16) -> Option<&IsoFieldData> {
+        self.iso_fields.get(&n)
+    }
 
-        if r.tags.get(&15).is_some() {
-            unreachable!();
+    pub fn set_iso_field(&mut self, n: u16, v: impl Into<IsoFieldData>) {
+        self.iso_fields.insert(n, v.into());
+    }
+
+    pub fn remove_iso_field(&mut self, n: u16) -> Option<IsoFieldData> {
+        self.iso_fields.remove(&n)
+    }
+
+    pub fn has_iso_field(&self, n: u16) -> bool {
+        self.iso_fields.contains_key(&n)
+    }
+
+    /// Numbers of present ISO fields in ascending order.
+    pub fn present_iso_fields(&self) -> Vec<u16> {
+        self.iso_fields.keys().copied().collect()
+    }
+
+    /// Parses amount ISO field `n`, e.g. 4 or 6, consisting of digits only.
+    pub fn iso_amount(&self, n: u16) -> Result<u64, Error> {
+        let name = Tag::Iso(n).to_string();
+        let data = self
+            .iso_fields
+            .get(&n)
+            .ok_or_else(|| Error::MissingField(name.clone()))?
+            .as_bytes();
+        if data.is_empty() || !data.iter().all(u8::is_ascii_digit) {
+            return Err(Error::incorrect_field_data(&name, "amount digits").got(data));
+        }
+        parse_ascii_bytes_lossy!(
+            data,
+            u64,
+            Error::incorrect_field_data(&name, "amount fitting u64").got(data)
+        )
+    }
+
+    /// Sets amount ISO field `n` zero-padded to [`ISO_AMOUNT_LEN`] digits.
+    pub fn set_iso_amount(&mut self, n: u16, amount: u64) -> Result<(), Error> {
+        let amount = format!("{:0width$}", amount, width = ISO_AMOUNT_LEN);
+        if amount.len() > ISO_AMOUNT_LEN {
+            return Err(Error::Bounds(format!(
+                "{} amount should contain at most {} digits, got {}",
+                Tag::Iso(n),
+                ISO_AMOUNT_LEN,
+                amount.len()
+            )));
         }
+        self.iso_fields.insert(n, amount.into());
+        Ok(())
+    }
 
-        assert_eq!(r.tags.get(&16).unwrap(), "74707182");
-        if r.tags.get(&17).is_some() {
-            unreachable!();
+    /// Inserts ISO fields from `defaults` which are missing in the request, present ones are kept as is.
+    pub fn apply_defaults(&mut self, defaults: &BTreeMap<u16, String>) {
+        for (n, v) in defaults {
+            self.iso_fields
+                .entry(*n)
+                .or_insert_with(|| v.clone().into());
         }
-        assert_eq!(r.tags.get(&18).unwrap(), "Y");
-        assert_eq!(r.tags.get(&22).unwrap(), "000000000010");
+    }
 
-        assert_eq!(r.iso_fields.get(&0).unwrap(), "0100");
+    /// ISO 8583 primary and secondary bitmaps of present ISO fields.
+    ///
+    /// Bit of field `n` is the `(n - 1) % 8`-th most significant bit of byte `(n - 1) / 8`.
+    /// Bit 1 is set if any of the fields 65-128 is present, i.e. the secondary bitmap is in use.
+    /// Field 0 (inner MTI) and fields above 128 have no bits and are skipped, use
+    /// [`SigmaRequest::to_iso_bitmap_checked`] to find out whether the latter are present.
+    pub fn to_iso_bitmap(&self) -> [u8; 16] {
+        self.to_iso_bitmap_checked().0
+    }
 
-        if r.iso_fields.get(&1).is_some() {
-            unreachable!();
+    /// Same as [`SigmaRequest::to_iso_bitmap`], but also returns numbers of present ISO fields above 128.
+    ///
+    /// Sigma allows ISO fields up to 9999, e.g. 101 and 102 fit in the secondary bitmap, but 130 has no bit in
+    /// either bitmap, so a non-empty list means the bitmap doesn't describe the message completely.
+    pub fn to_iso_bitmap_checked(&self) -> ([u8; 16], Vec<u16>) {
+        let mut bitmap = [0u8; 16];
+        let mut out_of_range = Vec::new();
+        for n in self.iso_fields.keys().copied() {
+            match n as usize {
+                0 => {}
+                n @ 1..=128 => {
+                    bitmap[(n - 1) / 8] |= 0x80 >> ((n - 1) % 8);
+                    if n > 64 {
+                        bitmap[0] |= 0x80;
+                    }
+                }
+                _ => out_of_range.push(n),
+            }
         }
+        (bitmap, out_of_range)
+    }
 
-        assert_eq!(r.iso_fields.get(&2).unwrap(), "555544******1111");
-        assert_eq!(r.iso_fields.get(&3).unwrap(), "500000");
-        assert_eq!(r.iso_fields.get(&4).unwrap(), "000100000000");
-        assert_eq!(r.iso_fields.get(&6).unwrap(), "000100000000");
-        assert_eq!(r.iso_fields.get(&7).unwrap(), "0629151748");
-        assert_eq!(r.iso_fields.get(&11).unwrap(), "100250");
-        assert_eq!(r.iso_fields.get(&12).unwrap(), "181748");
-        assert_eq!(r.iso_fields.get(&13).unwrap(), "0629");
-        assert_eq!(r.iso_fields.get(&18).unwrap(), "0000");
-        assert_eq!(r.iso_fields.get(&22).unwrap(), "0000");
-        assert_eq!(r.iso_fields.get(&25).unwrap(), "02");
-        assert_eq!(r.iso_fields.get(&32).unwrap(), "010455");
-        assert_eq!(r.iso_fields.get(&37).unwrap(), "002595100250");
-        assert_eq!(r.iso_fields.get(&41).unwrap(), "990");
-        assert_eq!(r.iso_fields.get(&42).unwrap(), "DCZ1");
-        assert_eq!(
-            r.iso_fields.get(&43).unwrap(),
-            "IDDQD Bank.                         GE"
-        );
-        assert_eq!(r.iso_fields.get(&48).unwrap(), "USRDT|2595100250");
-        assert_eq!(r.iso_fields.get(&49).unwrap(), "643");
-        assert_eq!(r.iso_fields.get(&51).unwrap(), "643");
-        assert_eq!(r.iso_fields.get(&60).unwrap(), "3");
-        assert_eq!(r.iso_fields.get(&101).unwrap(), "91926242");
-        assert_eq!(r.iso_fields.get(&102).unwrap(), "2371492071643");
+    pub fn iso_subfield(&self, n: u16, sn: u8) -> Option<&IsoFieldData> {
+        self.iso_subfields.get(&(n, sn))
     }
 
-    #[test]
-    fn serno_as_string() {
-        let payload = r#"{
-            "SAF": "Y",
-            "SRC": "M",
-            "MTI": "0200",
-            "Serno": "0600704097",
+    pub fn set_iso_subfield(&mut self, n: u16, sn: u8, v: impl Into<IsoFieldData>) {
+        self.iso_subfields.insert((n, sn), v.into());
+    }
+
+    pub fn remove_iso_subfield(&mut self, n: u16, sn: u8) -> Option<IsoFieldData> {
+        self.iso_subfields.remove(&(n, sn))
+    }
+
+    /// All subfields of ISO field `n` by their numbers.
+    pub fn iso_subfield_group(&self, n: u16) -> BTreeMap<u8, &IsoFieldData> {
+        self.iso_subfields
+            .range((n, u8::MIN)..=(n, u8::MAX))
+            .map(|((_, sn), v)| (*sn, v))
+            .collect()
+    }
+}
+
+impl TryFrom<Bytes> for SigmaRequest {
+    type Error = Error;
+
+    fn try_from(data: Bytes) -> Result<Self, Self::Error> {
+        Self::decode(data)
+    }
+}
+
+impl TryFrom<&[u8]> for SigmaRequest {
+    type Error = Error;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_slice(data)
+    }
+}
+
+/// Compact one-line summary of the request which does not include any field data, suitable for logging.
+impl Display for SigmaRequest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_fmt(format_args!(
+            "SigmaRequest[MTI={} SRC={} SAF={} serno={} tags={} iso_fields={} iso_subfields={}]",
+            self.mti,
+            self.source,
+            self.saf,
+            self.auth_serno,
+            self.tags.len(),
+            self.iso_fields.len(),
+            self.iso_subfields.len()
+        ))
+    }
+}
+
+impl Serialize for SigmaRequest {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json_value().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SigmaRequest {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        Self::from_json_value(value).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FeeData {
+    pub reason: u16,
+    pub currency: Currency,
+    pub amount: u64,
+}
+
+/// Number of digits of amount ISO fields, see [`SigmaRequest::set_iso_amount`].
+pub const ISO_AMOUNT_LEN: usize = 12;
+
+/// Length of single fee record in [`FeeData::parse_all`].
+pub const FEE_RECORD_LEN: usize = 19;
+
+impl FeeData {
+    /// Parses tag 32 data: 4 digit reason, 3 digit or 3 letter currency and amount of any length.
+    ///
+    /// Amount may be absent, i.e. data may be just 7 bytes long, in that case it is 0.
+    ///
+    /// Reason and amount must consist of ASCII digits only, so a record shifted by a short reason or a sign is
+    /// rejected with [`Error::IncorrectFieldData`] for `FeeData` instead of being parsed misaligned.
+    pub fn from_slice(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < 7 {
+            return Err(Error::incorrect_field_data(
+                "FeeData",
+                "at least 7 bytes: 4 digit reason and 3 digit or 3 letter currency",
+            )
+            .got(data));
+        }
+        // "\x00\x32\x00\x00\x108116978300"
+        if !data[..4].iter().all(u8::is_ascii_digit) || !data[7..].iter().all(u8::is_ascii_digit) {
+            return Err(Error::incorrect_field_data(
+                "FeeData",
+                "4 digit reason, 3 digit or 3 letter currency and amount digits",
+            )
+            .got(data));
+        }
+        let reason = parse_ascii_bytes_lossy!(
+            &data[0..4],
+            u16,
+            Error::incorrect_field_data("FeeData.reason", "valid integer").got(&data[0..4])
+        )?;
+        let currency = Currency::from_slice(&data[4..7])?;
+        let amount = match &data[7..] {
+            [] => 0,
+            amount => parse_ascii_bytes_lossy!(
+                amount,
+                u64,
+                Error::incorrect_field_data("FeeData.amount", "valid integer").got(amount)
+            )?,
+        };
+        Ok(Self {
+            reason,
+            currency,
+            amount,
+        })
+    }
+
+    /// Creates fee in the transaction currency of `req`, i.e. ISO field 49, which should be numeric.
+    pub fn for_request(req: &SigmaRequest, reason: u16, amount: u64) -> Result<Self, Error> {
+        let data = req
+            .iso_fields
+            .get(&49)
+            .ok_or_else(|| Error::MissingField("i049".into()))?
+            .as_bytes();
+        match Currency::from_slice(data) {
+            Ok(currency @ Currency::Numeric(_)) => Ok(Self {
+                reason,
+                currency,
+                amount,
+            }),
+            _ => Err(Error::incorrect_field_data("i049", "3 digits").got(data)),
+        }
+    }
+
+    /// Parses several fee records packed into single tag 32.
+    ///
+    /// Records are assumed to be [`FEE_RECORD_LEN`] bytes each: 4 digit reason, 3 digit or 3 letter currency and
+    /// amount zero-padded to 12 digits. Fails if the data length is not a multiple of the record length.
+    pub fn parse_all(data: &[u8]) -> Result<Vec<Self>, Error> {
+        let leftover = data.len() % FEE_RECORD_LEN;
+        if leftover != 0 {
+            return Err(Error::IncorrectData(format!(
+                "FeeData records should be {} bytes long, {} bytes left after the last one",
+                FEE_RECORD_LEN, leftover
+            )));
+        }
+        data.chunks(FEE_RECORD_LEN).map(Self::from_slice).collect()
+    }
+
+    pub fn encode(&self) -> Result<Bytes, Error> {
+        let mut buf = BytesMut::new();
+
+        if self.reason > 9999 {
+            return Err(Error::Bounds(
+                "FeeData.reason should be less or equal 9999".into(),
+            ));
+        }
+        buf.extend_from_slice(format!("{:<04}", self.reason).as_bytes());
+
+        self.currency.encode_to_buf(&mut buf)?;
+
+        buf.extend_from_slice(format!("{}", self.amount).as_bytes());
+
+        Ok(buf.freeze())
+    }
+
+    /// Same as [`FeeData::encode`], but fails if `amount` contains more than `max_amount_digits` digits.
+    pub fn encode_checked(&self, max_amount_digits: usize) -> Result<Bytes, Error> {
+        let amount_digits = self.amount.to_string().len();
+        if amount_digits > max_amount_digits {
+            return Err(Error::Bounds(format!(
+                "FeeData.amount should contain at most {} digits, got {}",
+                max_amount_digits, amount_digits
+            )));
+        }
+        self.encode()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Eq)]
+pub struct SigmaResponse {
+    mti: String,
+    /// Authorization serno. It is left public for advanced use, prefer [`SigmaResponse::set_auth_serno`] which rejects values longer than 10 digits.
+    pub auth_serno: u64,
+    pub reason: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fees: Vec<FeeData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Additional data (tag 48), it may be binary and is serialized same as [`IsoFieldData`].
+    pub adata: Option<IsoFieldData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supdata: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xri: Option<String>,
+    /// Regular tags other than the ones above, encoded after them unless the response is decoded.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra_tags: BTreeMap<u16, String>,
+    /// Raw encoded ISO fields and subfields, which are not recognized by [`SigmaResponse::decode`]. They are written back
+    /// as-is by [`SigmaResponse::encode`].
+    #[serde(skip)]
+    pub unparsed: Vec<u8>,
+    #[serde(skip)]
+    raw: Option<Bytes>,
+    #[serde(skip)]
+    layout: Option<Layout>,
+}
+
+/// Header and fields of a decoded [`SigmaResponse`] as they were received, so that [`SigmaResponse::encode`] writes
+/// unmodified fields back byte-for-byte and in their original order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Layout {
+    /// MTI and authorization serno.
+    header: Bytes,
+    /// Tags and whole encoded fields, including the tag and the length.
+    fields: Vec<(Tag, Bytes)>,
+}
+
+/// Compares the fields of the responses, the original bytes and field order kept by [`SigmaResponse::decode`] are ignored.
+impl PartialEq for SigmaResponse {
+    fn eq(&self, other: &Self) -> bool {
+        let Self {
+            mti,
+            auth_serno,
+            reason,
+            fees,
+            adata,
+            supdata,
+            xri,
+            extra_tags,
+            unparsed,
+            raw: _,
+            layout: _,
+        } = self;
+        *mti == other.mti
+            && *auth_serno == other.auth_serno
+            && *reason == other.reason
+            && *fees == other.fees
+            && *adata == other.adata
+            && *supdata == other.supdata
+            && *xri == other.xri
+            && *extra_tags == other.extra_tags
+            && *unparsed == other.unparsed
+    }
+}
+
+impl SigmaResponse {
+    pub fn new(mti: &str, auth_serno: u64, reason: u32) -> Result<Self, Error> {
+        validate_mti(mti)?;
+        Ok(Self {
+            mti: mti.into(),
+            auth_serno,
+            reason,
+            fees: Vec::new(),
+            adata: None,
+            supdata: None,
+            xri: None,
+            extra_tags: BTreeMap::new(),
+            unparsed: Vec::new(),
+            raw: None,
+            layout: None,
+        })
+    }
+
+    /// Original bytes of the decoded message including the length prefix, e.g. to forward it as is.
+    ///
+    /// It is `None` for responses which are not decoded and is not updated when the response is modified.
+    pub fn raw_bytes(&self) -> Option<&Bytes> {
+        self.raw.as_ref()
+    }
+
+    /// Creates response to `req` with its `auth_serno` and response MTI, e.g. `0210` for `0200`.
+    ///
+    /// Fails if `req` MTI has no defined response, see [`Mti::response_mti`].
+    pub fn for_request(req: &SigmaRequest, reason: u32) -> Result<Self, Error> {
+        let mti = req.mti_parsed().response_mti()?;
+        Self::new(&mti.to_string(), req.auth_serno, reason)
+    }
+
+    /// Builds response from JSON object with keys in the same style as [`SigmaRequest::from_json_value`]:
+    /// `MTI`, `Serno`, `Reason` and optional `Fees`, `AData`, `SupData`, `XRI` and extra tags, e.g. `T0005`.
+    ///
+    /// `Serno` and `Reason` may be numbers or strings with integers, `Fees` and `AData` are same as in serde representation.
+    pub fn from_json_value(mut data: Value) -> Result<SigmaResponse, Error> {
+        let data = data
+            .as_object_mut()
+            .ok_or_else(|| Error::IncorrectData("SigmaResponse JSON should be object".into()))?;
+
+        let mti = match data.remove("MTI") {
+            Some(Value::String(v)) => v,
+            Some(v) => {
+                return Err(Error::incorrect_field_data("MTI", "String").got(v.to_string()));
+            }
+            None => return Err(Error::MissingField("MTI".into())),
+        };
+        let auth_serno = match data.remove("Serno") {
+            Some(v) => json_u64("Serno", &v)?,
+            None => return Err(Error::MissingField("Serno".into())),
+        };
+        let reason = match data.remove("Reason") {
+            Some(v) => u32::try_from(json_u64("Reason", &v)?)
+                .map_err(|_| Error::incorrect_field_data("Reason", "u32").got(v.to_string()))?,
+            None => return Err(Error::MissingField("Reason".into())),
+        };
+        let mut resp = Self::new(&mti, auth_serno, reason)?;
+
+        macro_rules! fill_resp_field {
+            ($fname:ident, $pname:literal, $comment:literal) => {
+                if let Some(v) = data.remove($pname) {
+                    let got = v.to_string();
+                    resp.$fname = serde_json::from_value(v)
+                        .map_err(|_| Error::incorrect_field_data($pname, $comment).got(got))?;
+                }
+            };
+        }
+
+        fill_resp_field!(fees, "Fees", "array of fees");
+        fill_resp_field!(adata, "AData", "String or object with base64 raw data");
+        fill_resp_field!(supdata, "SupData", "String");
+        fill_resp_field!(xri, "XRI", "String");
+
+        for (key, value) in data.iter() {
+            match (Tag::from_str(key), value.as_str()) {
+                (Ok(Tag::Regular(n)), Some(v)) if !matches!(n, 31 | 32 | 33 | 48 | 50) => {
+                    resp.extra_tags.insert(n, v.into());
+                }
+                (Ok(Tag::Regular(_)), None) => {
+                    return Err(Error::incorrect_field_data(key, "String").got(value.to_string()));
+                }
+                _ => {
+                    return Err(Error::IncorrectData(format!(
+                        "Unknown SigmaResponse JSON key '{}'",
+                        key
+                    )));
+                }
+            }
+        }
+
+        Ok(resp)
+    }
+
+    /// Builds JSON representation of the response, which is accepted back by [`SigmaResponse::from_json_value`].
+    pub fn to_json_value(&self) -> Value {
+        let mut map = serde_json::Map::new();
+        map.insert("MTI".into(), Value::String(self.mti.clone()));
+        map.insert("Serno".into(), Value::from(self.auth_serno));
+        map.insert("Reason".into(), Value::from(self.reason));
+        if !self.fees.is_empty() {
+            map.insert(
+                "Fees".into(),
+                serde_json::to_value(&self.fees).expect("FeeData is always serializable"),
+            );
+        }
+        if let Some(adata) = &self.adata {
+            map.insert(
+                "AData".into(),
+                serde_json::to_value(adata).expect("IsoFieldData is always serializable"),
+            );
+        }
+        if let Some(supdata) = &self.supdata {
+            map.insert("SupData".into(), Value::String(supdata.clone()));
+        }
+        if let Some(xri) = &self.xri {
+            map.insert("XRI".into(), Value::String(xri.clone()));
+        }
+        for (n, v) in &self.extra_tags {
+            map.insert(Tag::Regular(*n).canonical_key(), Value::String(v.clone()));
+        }
+        Value::Object(map)
+    }
+
+    /// Decodes all responses from buffer containing concatenated length-prefixed messages.
+    pub fn decode_all(data: Bytes) -> Result<Vec<Self>, Error> {
+        decode_all(data, Self::decode)
+    }
+
+    /// Decodes the first message of `data`, returning it along with the bytes following it.
+    pub fn decode_one(data: Bytes) -> Result<(Self, Bytes), Error> {
+        decode_one(data, Self::decode)
+    }
+
+    /// Reads and decodes single message from blocking reader `r`.
+    #[cfg(feature = "std")]
+    pub fn read_from_sync<R: std::io::Read + ?Sized>(r: &mut R) -> Result<Self, Error> {
+        Self::decode(read_message(r)?)
+    }
+
+    pub fn decode(data: Bytes) -> Result<Self, Error> {
+        Self::decode_with_options(data, DecodeOptions::default())
+    }
+
+    /// Decodes the response from a borrowed slice, copying it, e.g. when it comes over an FFI boundary.
+    pub fn decode_slice(data: &[u8]) -> Result<Self, Error> {
+        Self::decode(Bytes::copy_from_slice(data))
+    }
+
+    /// Decodes message with length prefix of `len_width` digits instead of [`LENGTH_BYTES_COUNT`].
+    pub fn decode_with_len_width(data: Bytes, len_width: usize) -> Result<Self, Error> {
+        Self::decode_with_options(
+            data,
+            DecodeOptions {
+                len_width,
+                ..Default::default()
+            },
+        )
+    }
+
+    pub fn decode_with_options(mut data: Bytes, options: DecodeOptions) -> Result<Self, Error> {
+        let mut resp = Self::new("0100", 0, 0)?;
+
+        let src = data.clone();
+        let mut data = options.split_message_body(&mut data)?;
+        let msg_len = data.len();
+        resp.raw = Some(src.slice(..options.len_width + msg_len));
+
+        let mut layout = Layout {
+            header: data.slice(..RESPONSE_HEADER_LEN.min(data.len())),
+            fields: Vec::new(),
+        };
+        resp.set_mti(String::from_utf8_lossy(&bytes_split_to(&mut data, MTI_LEN)?).to_string())?;
+        resp.auth_serno = decode_auth_serno(&bytes_split_to(&mut data, SERNO_LEN)?)?;
+
+        let mut seen = BTreeSet::new();
+        while !data.is_empty() && !options.is_padding(&data) {
+            /*
+             *  |
+             *  |  T  | \x00 | \x31 | \x00 | \x00 | \x04 |  8  |  1  |  0  |  0  |
+             *        |             |      |             |                       |
+             *        |__ tag id ___|      |tag data len |_______ data __________|
+             */
+            let offset = msg_len - data.len();
+            let field_src = data.clone();
+            let (tag, data_src) = decode_field_from_cursor_at(&mut data, offset)?;
+            let field_len = field_src.len() - data.len();
+            layout
+                .fields
+                .push((tag.clone(), field_src.slice(..field_len)));
+
+            if let Tag::Regular(n @ (31 | 33 | 48 | 50)) = tag {
+                options.check_duplicate(&tag, !seen.insert(n), offset)?;
+            }
+            match tag {
+                Tag::Regular(31) => {
+                    resp.reason = parse_ascii_bytes_lossy!(
+                        &data_src,
+                        u32,
+                        Error::incorrect_field_data("reason", "u32").got(&data_src)
+                    )?;
+                }
+                Tag::Regular(32) => {
+                    resp.fees.push(FeeData::from_slice(&data_src)?);
+                }
+                Tag::Regular(33) => resp.xri = Some(String::from_utf8_lossy(&data_src).to_string()),
+                Tag::Regular(48) => {
+                    resp.adata = Some(options.field_data(data_src));
+                }
+                Tag::Regular(50) => {
+                    resp.supdata = Some(String::from_utf8_lossy(&data_src).to_string());
+                }
+                Tag::Regular(n) => {
+                    let duplicate = resp
+                        .extra_tags
+                        .insert(n, String::from_utf8_lossy(&data_src).to_string())
+                        .is_some();
+                    options.check_duplicate(&tag, duplicate, offset)?;
+                }
+                _ => {
+                    resp.unparsed.extend_from_slice(&field_src[..field_len]);
+                }
+            }
+        }
+
+        if options.require_reason && !seen.contains(&31) {
+            return Err(Error::MissingField("reason".into()));
+        }
+
+        resp.layout = Some(layout);
+        Ok(resp)
+    }
+
+    pub fn mti(&self) -> &str {
+        &self.mti
+    }
+
+    pub fn mti_parsed(&self) -> Mti {
+        self.mti.parse().expect("MTI is validated on assignment")
+    }
+
+    pub fn set_mti(&mut self, v: String) -> Result<(), Error> {
+        validate_mti(&v)?;
+        self.mti = v;
+        Ok(())
+    }
+
+    /// Sets authorization serno, failing with [`Error::Bounds`] if it is above [`MAX_AUTH_SERNO`].
+    pub fn set_auth_serno(&mut self, v: u64) -> Result<(), Error> {
+        validate_auth_serno(v)?;
+        self.auth_serno = v;
+        Ok(())
+    }
+
+    pub fn reason_code(&self) -> ReasonCode {
+        ReasonCode(self.reason)
+    }
+
+    /// Returns the response if its reason is one of `approve_codes`, otherwise [`DeclineError`] with the reason and
+    /// additional data, so business logic can use `?` on it. The fields remain accessible on the response itself.
+    pub fn to_result(&self, approve_codes: &[u32]) -> Result<&SigmaResponse, DeclineError> {
+        if approve_codes.contains(&self.reason) {
+            Ok(self)
+        } else {
+            Err(DeclineError {
+                reason: self.reason_code(),
+                adata: self.adata.clone(),
+            })
+        }
+    }
+
+    pub fn fees_by_currency(
+        &self,
+        currency: impl Into<Currency>,
+    ) -> impl Iterator<Item = &FeeData> {
+        let currency = currency.into();
+        self.fees.iter().filter(move |f| f.currency == currency)
+    }
+
+    /// Sum of all fee amounts in `currency`.
+    pub fn total_amount(&self, currency: impl Into<Currency>) -> u64 {
+        self.fees_by_currency(currency).map(|f| f.amount).sum()
+    }
+
+    /// Sorts fees by currency, then by reason (and by amount if both are equal), so they are encoded in deterministic order.
+    pub fn sort_fees(&mut self) {
+        self.fees
+            .sort_unstable_by_key(|f| (f.currency, f.reason, f.amount));
+    }
+
+    /// Encodes the response. Fails with [`Error::Bounds`] if `auth_serno` doesn't fit in 10 digits.
+    pub fn encode(&self) -> Result<Bytes, Error> {
+        self.encode_impl(false, LENGTH_BYTES_COUNT)
+    }
+
+    /// Encodes the response, keeping only first 10 digits of `auth_serno` if it is longer.
+    pub fn encode_truncating_auth_serno(&self) -> Result<Bytes, Error> {
+        self.encode_impl(true, LENGTH_BYTES_COUNT)
+    }
+
+    /// Encodes the response with length prefix of `len_width` digits instead of [`LENGTH_BYTES_COUNT`].
+    pub fn encode_with_len_width(&self, len_width: usize) -> Result<Bytes, Error> {
+        self.encode_impl(false, len_width)
+    }
+
+    /// Encodes the response appending it to `dst`, which is left intact on failure.
+    pub fn encode_to(&self, dst: &mut BytesMut) -> Result<(), Error> {
+        self.encode_to_impl(dst, false, LENGTH_BYTES_COUNT)
+    }
+
+    /// Encodes the response into an owned `Vec`, e.g. to hand it over an FFI boundary.
+    pub fn encode_vec(&self) -> Result<Vec<u8>, Error> {
+        Ok(self.encode()?.to_vec())
+    }
+
+    fn encode_impl(&self, truncate_auth_serno: bool, len_width: usize) -> Result<Bytes, Error> {
+        let mut buf = BytesMut::with_capacity(8192);
+        self.encode_to_impl(&mut buf, truncate_auth_serno, len_width)?;
+        Ok(buf.freeze())
+    }
+
+    fn encode_to_impl(
+        &self,
+        dst: &mut BytesMut,
+        truncate_auth_serno: bool,
+        len_width: usize,
+    ) -> Result<(), Error> {
+        encode_message_to_buf(dst, len_width, |buf| match self.layout {
+            Some(ref layout) => self.encode_layout_to(layout, truncate_auth_serno, buf),
+            None => {
+                self.encode_header_to(truncate_auth_serno, buf)?;
+                self.encode_tag_to(31, buf)?;
+                for fee in &self.fees {
+                    encode_fee_to(fee, buf)?;
+                }
+                for n in [48, 33].iter().chain(self.extra_tags.keys()) {
+                    self.encode_tag_to(*n, buf)?;
+                }
+                buf.extend_from_slice(&self.unparsed);
+                Ok(())
+            }
+        })
+    }
+
+    /// Encodes the response header: MTI and `auth_serno`.
+    fn encode_header_to(&self, truncate_auth_serno: bool, buf: &mut BytesMut) -> Result<(), Error> {
+        buf.extend_from_slice(self.mti.as_bytes());
+        encode_auth_serno_to_buf(self.auth_serno, truncate_auth_serno, buf)
+    }
+
+    /// Encodes current value of single-valued regular tag `n`, if it is set.
+    ///
+    /// `supdata` (T0050) is only decoded and is never encoded, T0050 of a decoded response is kept as is while it is
+    /// not modified.
+    fn encode_tag_to(&self, n: u16, buf: &mut BytesMut) -> Result<(), Error> {
+        let data: Option<Cow<'_, [u8]>> = match n {
+            31 => Some(Cow::Owned(self.reason.to_string().into_bytes())),
+            33 => self.xri.as_ref().map(|v| Cow::Borrowed(v.as_bytes())),
+            48 => self.adata.as_ref().map(|v| Cow::Borrowed(v.as_bytes())),
+            50 => None,
+            n => self.extra_tags.get(&n).map(|v| Cow::Borrowed(v.as_bytes())),
+        };
+        match data {
+            Some(data) => encode_field_to_buf(Tag::Regular(n), &data, buf),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks whether single-valued regular tag `n` still has the value decoded from `data`, or is still unset if
+    /// `data` is `None`, i.e. the tag was absent.
+    fn tag_unchanged(&self, n: u16, data: Option<&[u8]>) -> bool {
+        match (n, data) {
+            (31, Some(data)) => parse_ascii_bytes_lossy!(data, u32, ()) == Ok(self.reason),
+            // Reason of decoded response without the tag is 0
+            (31, None) => self.reason == 0,
+            (33, data) => self.xri.as_deref().map(str::as_bytes) == data,
+            (48, data) => self.adata.as_ref().map(IsoFieldData::as_bytes) == data,
+            (50, data) => self.supdata.as_deref().map(str::as_bytes) == data,
+            (n, data) => self.extra_tags.get(&n).map(String::as_bytes) == data,
+        }
+    }
+
+    /// Encodes decoded response keeping the original bytes and order of the fields which are not modified since.
+    ///
+    /// A modified single-valued tag is written in place of its first occurrence and a new one is written after all
+    /// the original fields. Fees are matched to the original ones by position, and ISO fields are written in place only
+    /// if [`SigmaResponse::unparsed`] is not modified.
+    fn encode_layout_to(
+        &self,
+        layout: &Layout,
+        truncate_auth_serno: bool,
+        buf: &mut BytesMut,
+    ) -> Result<(), Error> {
+        let (mti, serno) = layout.header.split_at(MTI_LEN.min(layout.header.len()));
+        if mti == self.mti.as_bytes() && decode_auth_serno(serno).ok() == Some(self.auth_serno) {
+            buf.extend_from_slice(&layout.header);
+        } else {
+            self.encode_header_to(truncate_auth_serno, buf)?;
+        }
+
+        // Value of a single-valued tag is taken from its last occurrence on decode
+        let mut last = BTreeMap::new();
+        let mut original_unparsed = Vec::new();
+        for (tag, field) in &layout.fields {
+            match tag {
+                Tag::Regular(32) => {}
+                Tag::Regular(n) => {
+                    last.insert(*n, &field[FIELD_HEADER_LEN..]);
+                }
+                _ => original_unparsed.extend_from_slice(field),
+            }
+        }
+        let unparsed_in_place = original_unparsed == self.unparsed;
+
+        let mut written = BTreeSet::new();
+        let mut fees = self.fees.iter();
+        for (tag, field) in &layout.fields {
+            match tag {
+                Tag::Regular(32) => match fees.next() {
+                    Some(fee)
+                        if FeeData::from_slice(&field[FIELD_HEADER_LEN..]).as_ref() == Ok(fee) =>
+                    {
+                        buf.extend_from_slice(field);
+                    }
+                    Some(fee) => encode_fee_to(fee, buf)?,
+                    None => {}
+                },
+                Tag::Regular(n) if self.tag_unchanged(*n, last.get(n).copied()) => {
+                    buf.extend_from_slice(field);
+                }
+                // Modified value replaces the first occurrence, the rest are dropped
+                Tag::Regular(n) if written.insert(*n) => self.encode_tag_to(*n, buf)?,
+                Tag::Regular(_) => {}
+                _ if unparsed_in_place => buf.extend_from_slice(field),
+                _ => {}
+            }
+        }
+
+        // Tags absent on decode, which are set since, are written in the same order as in a response which is not decoded
+        let added = |n: &u16| !last.contains_key(n) && !self.tag_unchanged(*n, None);
+        if added(&31) {
+            self.encode_tag_to(31, buf)?;
+        }
+        for fee in fees {
+            encode_fee_to(fee, buf)?;
+        }
+        for n in [48, 33].iter().chain(self.extra_tags.keys()) {
+            if added(n) {
+                self.encode_tag_to(*n, buf)?;
+            }
+        }
+        if !unparsed_in_place {
+            buf.extend_from_slice(&self.unparsed);
+        }
+        Ok(())
+    }
+}
+
+/// Encodes `fee` as tag 32.
+fn encode_fee_to(fee: &FeeData, buf: &mut BytesMut) -> Result<(), Error> {
+    encode_field_to_buf(Tag::Regular(32), &fee.encode()?, buf)
+}
+
+impl TryFrom<Bytes> for SigmaResponse {
+    type Error = Error;
+
+    fn try_from(data: Bytes) -> Result<Self, Self::Error> {
+        Self::decode(data)
+    }
+}
+
+impl TryFrom<&[u8]> for SigmaResponse {
+    type Error = Error;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        Self::decode_slice(data)
+    }
+}
+
+/// Compact one-line summary of the response, suitable for logging.
+impl Display for SigmaResponse {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_fmt(format_args!(
+            "SigmaResponse[MTI={} serno={} reason={} fees={}]",
+            self.mti,
+            self.auth_serno,
+            self.reason,
+            self.fees.len()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ok() {
+        let payload = r#"{
+            "SAF": "Y",
+            "SRC": "M",
+            "MTI": "0200",
+            "Serno": 6007040979,
+            "T0000": 2371492071643,
+            "T0001": "C",
+            "T0002": 643,
+            "T0003": "000100000000",
+            "T0004": 978,
+            "T0005": "000300000000",
+            "T0006": "OPS6",
+            "T0007": 19,
+            "T0008": 643,
+            "T0009": 3102,
+            "T0010": 3104,
+            "T0011": 2,
+            "T0014": "IDDQD Bank",
+            "T0016": 74707182,
+            "T0018": "Y",
+            "T0022": "000000000010",
+            "i000": "0100",
+            "i002": "555544******1111",
+            "i003": "500000",
+            "i004": "000100000000",
+            "i006": "000100000000",
+            "i007": "0629151748",
+            "i011": "100250",
+            "i012": "181748",
+            "i013": "0629",
+            "i018": "0000",
+            "i022": "0000",
+            "i025": "02",
+            "i032": "010455",
+            "i037": "002595100250",
+            "i041": 990,
+            "i042": "DCZ1",
+            "i043": "IDDQD Bank.                         GE",
+            "i048": "USRDT|2595100250",
+            "i049": 643,
+            "i051": 643,
+            "i060": 3,
+            "i101": 91926242,
+            "i102": 2371492071643
+        }"#;
+
+        let r: SigmaRequest =
+            SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
+        assert_eq!(r.saf, Saf::Yes);
+        assert_eq!(r.source, "M");
+        assert_eq!(r.mti, "0200");
+        assert_eq!(r.auth_serno, 6007040979);
+        assert_eq!(r.tags.get(&0).unwrap(), "2371492071643");
+        assert_eq!(r.tags.get(&1).unwrap(), "C");
+        assert_eq!(r.tags.get(&2).unwrap(), "643");
+        assert_eq!(r.tags.get(&3).unwrap(), "000100000000");
+        assert_eq!(r.tags.get(&4).unwrap(), "978");
+        assert_eq!(r.tags.get(&5).unwrap(), "000300000000");
+        assert_eq!(r.tags.get(&6).unwrap(), "OPS6");
+        assert_eq!(r.tags.get(&7).unwrap(), "19");
+        assert_eq!(r.tags.get(&8).unwrap(), "643");
+        assert_eq!(r.tags.get(&9).unwrap(), "3102");
+        assert_eq!(r.tags.get(&10).unwrap(), "3104");
+        assert_eq!(r.tags.get(&11).unwrap(), "2");
+
+        if r.tags.contains_key(&12) {
+            unreachable!();
+        }
+
+        if r.tags.contains_key(&13) {
+            unreachable!();
+        }
+
+        assert_eq!(r.tags.get(&14).unwrap(), "IDDQD Bank");
+
+        if r.tags.contains_key(&15) {
+            unreachable!();
+        }
+
+        assert_eq!(r.tags.get(&16).unwrap(), "74707182");
+        if r.tags.contains_key(&17) {
+            unreachable!();
+        }
+        assert_eq!(r.tags.get(&18).unwrap(), "Y");
+        assert_eq!(r.tags.get(&22).unwrap(), "000000000010");
+
+        assert_eq!(r.iso_fields.get(&0).unwrap(), "0100");
+
+        if r.iso_fields.contains_key(&1) {
+            unreachable!();
+        }
+
+        assert_eq!(r.iso_fields.get(&2).unwrap(), "555544******1111");
+        assert_eq!(r.iso_fields.get(&3).unwrap(), "500000");
+        assert_eq!(r.iso_fields.get(&4).unwrap(), "000100000000");
+        assert_eq!(r.iso_fields.get(&6).unwrap(), "000100000000");
+        assert_eq!(r.iso_fields.get(&7).unwrap(), "0629151748");
+        assert_eq!(r.iso_fields.get(&11).unwrap(), "100250");
+        assert_eq!(r.iso_fields.get(&12).unwrap(), "181748");
+        assert_eq!(r.iso_fields.get(&13).unwrap(), "0629");
+        assert_eq!(r.iso_fields.get(&18).unwrap(), "0000");
+        assert_eq!(r.iso_fields.get(&22).unwrap(), "0000");
+        assert_eq!(r.iso_fields.get(&25).unwrap(), "02");
+        assert_eq!(r.iso_fields.get(&32).unwrap(), "010455");
+        assert_eq!(r.iso_fields.get(&37).unwrap(), "002595100250");
+        assert_eq!(r.iso_fields.get(&41).unwrap(), "990");
+        assert_eq!(r.iso_fields.get(&42).unwrap(), "DCZ1");
+        assert_eq!(
+            r.iso_fields.get(&43).unwrap(),
+            "IDDQD Bank.                         GE"
+        );
+        assert_eq!(r.iso_fields.get(&48).unwrap(), "USRDT|2595100250");
+        assert_eq!(r.iso_fields.get(&49).unwrap(), "643");
+        assert_eq!(r.iso_fields.get(&51).unwrap(), "643");
+        assert_eq!(r.iso_fields.get(&60).unwrap(), "3");
+        assert_eq!(r.iso_fields.get(&101).unwrap(), "91926242");
+        assert_eq!(r.iso_fields.get(&102).unwrap(), "2371492071643");
+    }
+
+    #[test]
+    fn serno_as_string() {
+        let payload = r#"{
+            "SAF": "Y",
+            "SRC": "M",
+            "MTI": "0200",
+            "Serno": "0600704097",
             "T0000": 2371492071643,
             "T0001": "C",
             "T0002": 643,
@@ -705,478 +2313,2178 @@ mod tests {
             "i102": 2371492071643
         }"#;
 
-        let r: SigmaRequest =
-            SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
-        assert_eq!(r.saf, "Y");
-        assert_eq!(r.source, "M");
-        assert_eq!(r.mti, "0200");
-        assert_eq!(r.auth_serno, 600704097);
-        assert_eq!(r.tags.get(&0).unwrap(), "2371492071643");
-        assert_eq!(r.tags.get(&1).unwrap(), "C");
-        assert_eq!(r.tags.get(&2).unwrap(), "643");
-        assert_eq!(r.tags.get(&3).unwrap(), "000100000000");
-        assert_eq!(r.tags.get(&4).unwrap(), "978");
-        assert_eq!(r.tags.get(&5).unwrap(), "000300000000");
-        assert_eq!(r.tags.get(&6).unwrap(), "OPS6");
-        assert_eq!(r.tags.get(&7).unwrap(), "19");
-        assert_eq!(r.tags.get(&8).unwrap(), "643");
-        assert_eq!(r.tags.get(&9).unwrap(), "3102");
-        assert_eq!(r.tags.get(&10).unwrap(), "3104");
-        assert_eq!(r.tags.get(&11).unwrap(), "2");
+        let r: SigmaRequest =
+            SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
+        assert_eq!(r.saf, Saf::Yes);
+        assert_eq!(r.source, "M");
+        assert_eq!(r.mti, "0200");
+        assert_eq!(r.auth_serno, 600704097);
+        assert_eq!(r.tags.get(&0).unwrap(), "2371492071643");
+        assert_eq!(r.tags.get(&1).unwrap(), "C");
+        assert_eq!(r.tags.get(&2).unwrap(), "643");
+        assert_eq!(r.tags.get(&3).unwrap(), "000100000000");
+        assert_eq!(r.tags.get(&4).unwrap(), "978");
+        assert_eq!(r.tags.get(&5).unwrap(), "000300000000");
+        assert_eq!(r.tags.get(&6).unwrap(), "OPS6");
+        assert_eq!(r.tags.get(&7).unwrap(), "19");
+        assert_eq!(r.tags.get(&8).unwrap(), "643");
+        assert_eq!(r.tags.get(&9).unwrap(), "3102");
+        assert_eq!(r.tags.get(&10).unwrap(), "3104");
+        assert_eq!(r.tags.get(&11).unwrap(), "2");
+
+        if r.tags.contains_key(&12) {
+            unreachable!();
+        }
+
+        if r.tags.contains_key(&13) {
+            unreachable!();
+        }
+
+        assert_eq!(r.tags.get(&14).unwrap(), "IDDQD Bank");
+
+        if r.tags.contains_key(&15) {
+            unreachable!();
+        }
+
+        assert_eq!(r.tags.get(&16).unwrap(), "74707182");
+        if r.tags.contains_key(&17) {
+            unreachable!();
+        }
+        assert_eq!(r.tags.get(&18).unwrap(), "Y");
+        assert_eq!(r.tags.get(&22).unwrap(), "000000000010");
+        assert_eq!(r.tags.get(&23).unwrap(), "X-Request-Id");
+
+        assert_eq!(r.iso_fields.get(&0).unwrap(), "0100");
+
+        if r.iso_fields.contains_key(&1) {
+            unreachable!();
+        }
+
+        assert_eq!(r.iso_fields.get(&2).unwrap(), "555544******1111");
+        assert_eq!(r.iso_fields.get(&3).unwrap(), "500000");
+        assert_eq!(r.iso_fields.get(&4).unwrap(), "000100000000");
+        assert_eq!(r.iso_fields.get(&6).unwrap(), "000100000000");
+        assert_eq!(r.iso_fields.get(&7).unwrap(), "0629151748");
+        assert_eq!(r.iso_fields.get(&11).unwrap(), "100250");
+        assert_eq!(r.iso_fields.get(&12).unwrap(), "181748");
+        assert_eq!(r.iso_fields.get(&13).unwrap(), "0629");
+        assert_eq!(r.iso_fields.get(&18).unwrap(), "0000");
+        assert_eq!(r.iso_fields.get(&22).unwrap(), "0000");
+        assert_eq!(r.iso_fields.get(&25).unwrap(), "02");
+        assert_eq!(r.iso_fields.get(&32).unwrap(), "010455");
+        assert_eq!(r.iso_fields.get(&37).unwrap(), "002595100250");
+        assert_eq!(r.iso_fields.get(&41).unwrap(), "990");
+        assert_eq!(r.iso_fields.get(&42).unwrap(), "DCZ1");
+        assert_eq!(
+            r.iso_fields.get(&43).unwrap(),
+            "IDDQD Bank.                         GE"
+        );
+        assert_eq!(r.iso_fields.get(&48).unwrap(), "USRDT|2595100250");
+        assert_eq!(r.iso_fields.get(&49).unwrap(), "643");
+        assert_eq!(r.iso_fields.get(&51).unwrap(), "643");
+        assert_eq!(r.iso_fields.get(&60).unwrap(), "3");
+        assert_eq!(r.iso_fields.get(&101).unwrap(), "91926242");
+        assert_eq!(r.iso_fields.get(&102).unwrap(), "2371492071643");
+    }
+
+    #[test]
+    fn missing_saf() {
+        let payload = r#"{
+            "SRC": "M",
+            "MTI": "0200"
+        }"#;
+
+        if SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).is_ok() {
+            unreachable!("Should not return Ok if mandatory field is missing");
+        }
+    }
+
+    #[test]
+    fn invalid_saf() {
+        let payload = r#"{
+        	"SAF": 1234,
+            "SRC": "M",
+            "MTI": "0200"
+        }"#;
+
+        if SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).is_ok() {
+            unreachable!("Should not return Ok if the filed has invalid format");
+        }
+    }
+
+    #[test]
+    fn missing_source() {
+        let payload = r#"{
+        	"SAF": "N",
+            "MTI": "0200"
+        }"#;
+
+        if SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).is_ok() {
+            unreachable!("Should not return Ok if mandatory field is missing");
+        }
+    }
+
+    #[test]
+    fn invalid_source() {
+        let payload = r#"{
+        	"SAF": "N",
+            "SRC": 929292,
+            "MTI": "0200"
+        }"#;
+
+        if SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).is_ok() {
+            unreachable!("Should not return Ok if the filed has invalid format");
+        }
+    }
+
+    #[test]
+    fn missing_mti() {
+        let payload = r#"{
+        	"SAF": "N",
+        	"SRC": "O"
+        }"#;
+
+        if SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).is_ok() {
+            unreachable!("Should not return Ok if mandatory field is missing");
+        }
+    }
+
+    #[test]
+    fn invalid_mti() {
+        let payload = r#"{
+        	"SAF": "N",
+            "SRC": "O",
+            "MTI": 1200
+        }"#;
+
+        if SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).is_ok() {
+            unreachable!("Should not return Ok if the filed has invalid format");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn generating_auth_serno() {
+        let payload = r#"{
+                "SAF": "Y",
+                "SRC": "M",
+                "MTI": "0200",
+                "T0000": "02371492071643"
+            }"#;
+
+        let r: SigmaRequest =
+            SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
+        assert!(
+            r.auth_serno > 0,
+            "Should generate authorization serno if the field is missing"
+        );
+        assert!(
+            r.auth_serno <= MAX_AUTH_SERNO,
+            "Generated authorization serno should fit in 10 digits"
+        );
+        assert!(r.encode().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn generating_auth_serno_with_rng() {
+        use rand::SeedableRng;
+
+        let payload = r#"{
+                "SAF": "Y",
+                "SRC": "M",
+                "MTI": "0200"
+            }"#;
+
+        let expected = gen_random_auth_serno_with(&mut rand::rngs::StdRng::seed_from_u64(42));
+        let r = SigmaRequest::from_json_value_with_rng(
+            serde_json::from_str(payload).unwrap(),
+            JsonOptions::default(),
+            &mut rand::rngs::StdRng::seed_from_u64(42),
+        )
+        .unwrap();
+        assert_eq!(r.auth_serno, expected);
+    }
+
+    #[test]
+    fn encode_generated_auth_serno() {
+        let payload = r#"{
+                "SAF": "Y",
+                "SRC": "M",
+                "MTI": "0201",
+                "Serno": 7877706965687192023
+            }"#;
+
+        let r: SigmaRequest =
+            SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
+        assert!(
+            matches!(r.encode(), Err(Error::Bounds(_))),
+            "Auth serno longer than 10 digits should not be silently trimmed"
+        );
+        let serialized = r.encode_truncating_auth_serno().unwrap();
+        assert_eq!(
+            serialized,
+            b"00016YM02017877706965"[..],
+            "Original auth serno should be trimmed to 10 bytes"
+        );
+    }
+
+    #[test]
+    fn encode_sigma_response_long_auth_serno() {
+        let resp = SigmaResponse::new("0110", 12345678901, 8100).unwrap();
+
+        assert!(matches!(resp.encode(), Err(Error::Bounds(_))));
+        assert_eq!(
+            resp.encode_truncating_auth_serno().unwrap()[..],
+            b"0002401101234567890T\x00\x31\x00\x00\x048100"[..]
+        );
+    }
+
+    #[test]
+    fn encode_sigma_request_tag_out_of_range() {
+        let mut r = SigmaRequest::new("N", "M", "0200", 1).unwrap();
+        r.set_tag(10000, "X");
+
+        assert_eq!(r.encode(), Err(Error::TagOutOfRange(Tag::Regular(10000))));
+    }
+
+    #[test]
+    fn sigma_request_encoded_len() {
+        let mut r = SigmaRequest::new("Y", "M", "0200", 1).unwrap();
+        assert_eq!(r.encoded_len(), 21);
+        assert_eq!(r.encode().unwrap().len(), r.encoded_len());
+
+        r.set_tag(6, "OPS6");
+        r.set_iso_field(2, "555544******1111");
+        r.set_iso_subfield(48, 1, "A".repeat(9999));
+        let encoded = r.encode().unwrap();
+        assert_eq!(encoded.len(), r.encoded_len());
+        assert_eq!(r.encoded_len(), 21 + 10 + 22 + 10005);
+        assert_eq!(
+            r.encode_with_len_width(6).unwrap().len(),
+            r.encoded_len() + 1
+        );
+    }
+
+    #[test]
+    fn encode_sigma_request_to_buf() {
+        let r = SigmaRequest::new("Y", "M", "0200", 1).unwrap();
+        let mut buf = BytesMut::new();
+        r.encode_to(&mut buf).unwrap();
+        assert_eq!(buf, r.encode().unwrap());
+
+        let mut r = SigmaRequest::new("Y", "M", "0200", 12345678901).unwrap();
+        assert!(r.encode_to(&mut buf).is_err());
+        assert_eq!(buf, b"00016YM02000000000001"[..]);
+
+        r.auth_serno = 1;
+        for i in 48..58 {
+            r.set_tag(i, "A".repeat(9999));
+        }
+        assert!(matches!(r.encode_to(&mut buf), Err(Error::Bounds(_))));
+        assert_eq!(buf, b"00016YM02000000000001"[..]);
+    }
+
+    #[test]
+    fn encode_sigma_requests_to_shared_buf() {
+        let mut first = SigmaRequest::new("Y", "M", "0200", 1).unwrap();
+        first.set_tag(6, "OPS6");
+        let mut second = SigmaRequest::new("N", "O", "0120", 2).unwrap();
+        second.set_iso_field(2, "555544******1111");
+
+        let mut buf = BytesMut::new();
+        first.encode_to(&mut buf).unwrap();
+        second.encode_to(&mut buf).unwrap();
+
+        assert_eq!(
+            SigmaRequest::decode_all(buf.freeze()).unwrap(),
+            vec![first, second]
+        );
+    }
+
+    #[test]
+    fn encode_sigma_request() {
+        let payload = r#"{
+                "SAF": "Y",
+                "SRC": "M",
+                "MTI": "0200",
+                "Serno": 6007040979,
+                "T0000": 2371492071643,
+                "T0001": "C",
+                "T0002": 643,
+                "T0003": "000100000000",
+                "T0004": 978,
+                "T0005": "000300000000",
+                "T0006": "OPS6",
+                "T0007": 19,
+                "T0008": 643,
+                "T0009": 3102,
+                "T0010": 3104,
+                "T0011": 2,
+                "T0014": "IDDQD Bank",
+                "T0016": 74707182,
+                "T0018": "Y",
+                "T0022": "000000000010",
+                "i000": "0100",
+                "i002": "555544******1111",
+                "i003": "500000",
+                "i004": "000100000000",
+                "i006": "000100000000",
+                "i007": "0629151748",
+                "i011": "100250",
+                "i012": "181748",
+                "i013": "0629",
+                "i018": "0000",
+                "i022": "0000",
+                "i025": "02",
+                "i032": "010455",
+                "i037": "002595100250",
+                "i041": 990,
+                "i042": "DCZ1",
+                "i043": "IDDQD Bank.                         GE",
+                "i048": "USRDT|2595100250",
+                "i049": 643,
+                "i051": 643,
+                "i060": 3,
+                "i101": 91926242,
+                "i102": 2371492071643
+            }"#;
+
+        let r: SigmaRequest =
+            SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
+        let serialized = r.encode().unwrap();
+        assert_eq!(
+            serialized,
+            b"00536YM02006007040979T\x00\x00\x00\x00\x132371492071643T\x00\x01\x00\x00\x01CT\x00\x02\x00\x00\x03643T\x00\x03\x00\x00\x12000100000000T\x00\x04\x00\x00\x03978T\x00\x05\x00\x00\x12000300000000T\x00\x06\x00\x00\x04OPS6T\x00\x07\x00\x00\x0219T\x00\x08\x00\x00\x03643T\x00\t\x00\x00\x043102T\x00\x10\x00\x00\x043104T\x00\x11\x00\x00\x012T\x00\x14\x00\x00\x10IDDQD BankT\x00\x16\x00\x00\x0874707182T\x00\x18\x00\x00\x01YT\x00\x22\x00\x00\x12000000000010I\x00\x00\x00\x00\x040100I\x00\x02\x00\x00\x16555544******1111I\x00\x03\x00\x00\x06500000I\x00\x04\x00\x00\x12000100000000I\x00\x06\x00\x00\x12000100000000I\x00\x07\x00\x00\x100629151748I\x00\x11\x00\x00\x06100250I\x00\x12\x00\x00\x06181748I\x00\x13\x00\x00\x040629I\x00\x18\x00\x00\x040000I\x00\"\x00\x00\x040000I\x00%\x00\x00\x0202I\x002\x00\x00\x06010455I\x007\x00\x00\x12002595100250I\x00A\x00\x00\x03990I\x00B\x00\x00\x04DCZ1I\x00C\x00\x008IDDQD Bank.                         GEI\x00H\x00\x00\x16USRDT|2595100250I\x00I\x00\x00\x03643I\x00Q\x00\x00\x03643I\x00`\x00\x00\x013I\x01\x01\x00\x00\x0891926242I\x01\x02\x00\x00\x132371492071643"[..]
+        );
+    }
+
+    #[test]
+    fn decode_sigma_request() {
+        let src = Bytes::from_static(b"00545YM02006007040979T\x00\x00\x00\x00\x132371492071643T\x00\x01\x00\x00\x01CT\x00\x02\x00\x00\x03643T\x00\x03\x00\x00\x12000100000000T\x00\x04\x00\x00\x03978T\x00\x05\x00\x00\x12000300000000T\x00\x06\x00\x00\x04OPS6T\x00\x07\x00\x00\x0219T\x00\x08\x00\x00\x03643T\x00\t\x00\x00\x043102T\x00\x10\x00\x00\x043104T\x00\x11\x00\x00\x012T\x00\x14\x00\x00\x10IDDQD BankT\x00\x16\x00\x00\x0874707182T\x00\x18\x00\x00\x01YT\x00\x22\x00\x00\x12000000000010T\x00\x50\x00\x00\x03123I\x00\x00\x00\x00\x040100I\x00\x02\x00\x00\x16555544******1111I\x00\x03\x00\x00\x06500000I\x00\x04\x00\x00\x12000100000000I\x00\x06\x00\x00\x12000100000000I\x00\x07\x00\x00\x100629151748I\x00\x11\x00\x00\x06100250I\x00\x12\x00\x00\x06181748I\x00\x13\x00\x00\x040629I\x00\x18\x00\x00\x040000I\x00\"\x00\x00\x040000I\x00%\x00\x00\x0202I\x002\x00\x00\x06010455I\x007\x00\x00\x12002595100250I\x00A\x00\x00\x03990I\x00B\x00\x00\x04DCZ1I\x00C\x00\x008IDDQD Bank.                         GEI\x00H\x00\x00\x16USRDT|2595100250I\x00I\x00\x00\x03643I\x00Q\x00\x00\x03643I\x00`\x00\x00\x013I\x01\x01\x00\x00\x0891926242I\x01\x02\x00\x00\x132371492071643");
+        let json = r#"{
+                "SAF": "Y",
+                "SRC": "M",
+                "MTI": "0200",
+                "Serno": 6007040979,
+                "T0000": 2371492071643,
+                "T0001": "C",
+                "T0002": 643,
+                "T0003": "000100000000",
+                "T0004": 978,
+                "T0005": "000300000000",
+                "T0006": "OPS6",
+                "T0007": 19,
+                "T0008": 643,
+                "T0009": 3102,
+                "T0010": 3104,
+                "T0011": 2,
+                "T0014": "IDDQD Bank",
+                "T0016": 74707182,
+                "T0018": "Y",
+                "T0022": "000000000010",
+                "T0050": "123",
+                "i000": "0100",
+                "i002": "555544******1111",
+                "i003": "500000",
+                "i004": "000100000000",
+                "i006": "000100000000",
+                "i007": "0629151748",
+                "i011": "100250",
+                "i012": "181748",
+                "i013": "0629",
+                "i018": "0000",
+                "i022": "0000",
+                "i025": "02",
+                "i032": "010455",
+                "i037": "002595100250",
+                "i041": 990,
+                "i042": "DCZ1",
+                "i043": "IDDQD Bank.                         GE",
+                "i048": "USRDT|2595100250",
+                "i049": 643,
+                "i051": 643,
+                "i060": 3,
+                "i101": 91926242,
+                "i102": 2371492071643
+            }"#;
+
+        let target: SigmaRequest =
+            SigmaRequest::from_json_value(serde_json::from_str(json).unwrap()).unwrap();
+
+        let req = SigmaRequest::decode(src).unwrap();
+
+        assert_eq!(req, target);
+    }
+
+    #[test]
+    fn decode_sigma_request_shares_buffer() {
+        let src = Bytes::from_static(b"00032YM02006007040979I\x00\x02\x00\x00\x105555441111");
+        assert!(matches!(
+            SigmaRequest::decode(src.clone()).unwrap().iso_field(2),
+            Some(IsoFieldData::String(v)) if v == "5555441111"
+        ));
+
+        let options = DecodeOptions {
+            share_buffer: true,
+            ..Default::default()
+        };
+        let req = SigmaRequest::decode_with_options(src.clone(), options).unwrap();
+        match req.iso_field(2).unwrap() {
+            IsoFieldData::Bytes(b) => {
+                assert_eq!(b, &b"5555441111"[..]);
+                assert_eq!(b.as_ptr(), src[27..].as_ptr());
+            }
+            other => unreachable!("Should not copy field data: {:?}", other),
+        }
+        assert_eq!(req.iso_field(2).unwrap(), &IsoFieldData::from("5555441111"));
+    }
+
+    #[test]
+    fn sigma_request_json_roundtrip() {
+        let payload = r#"{
+                "SAF": "Y",
+                "SRC": "M",
+                "MTI": "0200",
+                "Serno": 6007040979,
+                "T0000": 2371492071643,
+                "T0014": "IDDQD Bank",
+                "i000": "0100",
+                "i002": "555544******1111",
+                "s002401": "A",
+                "s002412": "B"
+            }"#;
+
+        let r: SigmaRequest =
+            SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
+
+        let value = r.to_json_value();
+        assert_eq!(value["SAF"], "Y");
+        assert_eq!(value["SRC"], "M");
+        assert_eq!(value["MTI"], "0200");
+        assert_eq!(value["Serno"], 6007040979u64);
+        assert_eq!(value["T0000"], "2371492071643");
+        assert_eq!(value["T0014"], "IDDQD Bank");
+        assert_eq!(value["i000"], "0100");
+        assert_eq!(value["i002"], "555544******1111");
+        assert_eq!(value["s002401"], "A");
+        assert_eq!(value["s002412"], "B");
+
+        assert_eq!(SigmaRequest::from_json_value(value).unwrap(), r);
+
+        let serialized = serde_json::to_string(&r).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SigmaRequest>(&serialized).unwrap(),
+            r
+        );
+    }
+
+    #[test]
+    fn sigma_request_field_accessors() {
+        let mut r = SigmaRequest::new("N", "M", "0200", 1).unwrap();
+
+        r.set_tag(6, "OPS6");
+        r.set_iso_field(2, "555544******1111");
+        r.set_iso_field(55, &b"\x9f\x02"[..]);
+        r.set_iso_subfield(48, 1, "A");
+
+        assert_eq!(r.tag(6), Some("OPS6"));
+        assert_eq!(r.tag(7), None);
+        assert_eq!(r.iso_field(2).unwrap(), "555544******1111");
+        assert_eq!(r.iso_field(55).unwrap(), b"\x9f\x02");
+        assert_eq!(r.iso_subfield(48, 1).unwrap(), "A");
+        assert!(r.iso_subfield(48, 2).is_none());
+
+        assert_eq!(r.remove_tag(6), Some("OPS6".to_string()));
+        assert_eq!(r.remove_iso_field(2), Some("555544******1111".into()));
+        assert_eq!(r.remove_iso_subfield(48, 1), Some("A".into()));
+        assert!(r.remove_iso_field(2).is_none());
+        assert!(r.tags.is_empty());
+        assert_eq!(r.iso_fields.len(), 1);
+        assert!(r.iso_subfields.is_empty());
+    }
+
+    #[test]
+    fn sigma_request_fields() {
+        let mut r = SigmaRequest::new("N", "M", "0200", 1).unwrap();
+        r.set_iso_subfield(48, 1, "S");
+        r.set_iso_field(2, "I2");
+        r.set_tag(14, "T14");
+        r.set_tag(6, "T6");
+
+        assert_eq!(
+            r.fields().collect::<Vec<_>>(),
+            vec![
+                (Tag::Regular(6), &b"T6"[..]),
+                (Tag::Regular(14), &b"T14"[..]),
+                (Tag::Iso(2), &b"I2"[..]),
+                (Tag::IsoSubfield(48, 1), &b"S"[..]),
+            ]
+        );
+    }
+
+    #[test]
+    fn sigma_request_mask_pan() {
+        let mut r = SigmaRequest::new("N", "M", "0200", 1).unwrap();
+        assert_eq!(r.masked(), r);
+
+        r.set_iso_field(2, "5555441234561111");
+        let masked = r.masked();
+        assert_eq!(masked.iso_field(2).unwrap(), "555544******1111");
+        assert_eq!(r.iso_field(2).unwrap(), "5555441234561111");
+
+        r.mask_pan();
+        assert_eq!(r.iso_field(2).unwrap(), "555544******1111");
+
+        r.set_iso_field(2, "5555441111");
+        r.mask_pan();
+        assert_eq!(r.iso_field(2).unwrap(), "5555441111");
+
+        r.set_iso_field(2, "55554ÿ12345611ÿ1");
+        r.mask_pan();
+        assert_eq!(r.iso_field(2).unwrap(), "55554ÿ******11ÿ1");
+    }
+
+    #[test]
+    fn sigma_request_validate_consistency() {
+        let mut r = SigmaRequest::new("N", "M", "0200", 1).unwrap();
+        assert!(r.validate_consistency().is_ok());
+
+        r.set_iso_field(0, "0100");
+        assert!(r.validate_consistency().is_ok());
+
+        r.set_iso_field(0, "100");
+        r.auth_serno = 12345678901;
+        assert_eq!(
+            r.validate_consistency(),
+            Err(Error::IncorrectData(
+                "i000 should be 4 digit number (string); Serno '12345678901' contains more than 10 digits".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn sigma_request_mti_parsed() {
+        let r = SigmaRequest::new("N", "M", "0200", 1).unwrap();
+        assert_eq!(r.mti_parsed().class(), 2);
+        assert_eq!(r.mti_parsed().response_mti().unwrap().to_string(), "0210");
+    }
+
+    #[test]
+    fn sigma_request_subfield_json_roundtrip() {
+        let payload = r#"{
+                "SAF": "Y",
+                "SRC": "M",
+                "MTI": "0200",
+                "Serno": 6007040979,
+                "s004801": "USRDT",
+                "S004802": "2595100250"
+            }"#;
+
+        let r: SigmaRequest =
+            SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
+        assert_eq!(r.iso_subfield(48, 1).unwrap(), "USRDT");
+        assert_eq!(r.iso_subfield(48, 2).unwrap(), "2595100250");
+
+        let encoded = r.encode().unwrap();
+        assert_eq!(
+            encoded[..],
+            b"00043YM02006007040979S\x00\x48\x01\x00\x05USRDTS\x00\x48\x02\x00\x102595100250"[..]
+        );
+
+        let decoded = SigmaRequest::decode(encoded).unwrap();
+        assert_eq!(decoded, r);
+
+        let value = decoded.to_json_value();
+        assert_eq!(value["s004801"], "USRDT");
+        assert_eq!(value["s004802"], "2595100250");
+        assert_eq!(SigmaRequest::from_json_value(value).unwrap(), r);
+    }
+
+    #[test]
+    fn decode_sigma_response() {
+        let s = Bytes::from_static(b"0002401104007040978T\x00\x31\x00\x00\x048495");
+
+        let resp = SigmaResponse::decode(s).unwrap();
+        assert_eq!(resp.mti, "0110");
+        assert_eq!(resp.auth_serno, 4007040978);
+        assert_eq!(resp.reason, 8495);
+        assert!(resp.reason_code().is_decline(&[8100..=8199]));
+
+        let serialized = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"mti":"0110","auth_serno":4007040978,"reason":8495}"#
+        );
+    }
+
+    #[test]
+    fn sigma_response_to_result() {
+        let mut resp = SigmaResponse::new("0110", 4007040978, 8495).unwrap();
+        resp.adata = Some("DECLINED".into());
+        assert_eq!(
+            resp.to_result(&[8100, 8101]),
+            Err(DeclineError {
+                reason: ReasonCode(8495),
+                adata: Some("DECLINED".into()),
+            })
+        );
+
+        resp.reason = 8101;
+        assert_eq!(resp.to_result(&[8100, 8101]), Ok(&resp));
+        assert!(resp.to_result(&[]).is_err());
+    }
+
+    #[test]
+    fn decode_sigma_response_xri() {
+        let s = Bytes::from_static(
+            b"0004201104007040978T\x00\x31\x00\x00\x048495T\x00\x33\x00\x00\x12X-Request-Id",
+        );
+
+        let resp = SigmaResponse::decode(s).unwrap();
+        assert_eq!(resp.mti, "0110");
+        assert_eq!(resp.auth_serno, 4007040978);
+        assert_eq!(resp.reason, 8495);
+        assert_eq!(resp.xri, Some("X-Request-Id".to_string()));
+
+        let serialized = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"mti":"0110","auth_serno":4007040978,"reason":8495,"xri":"X-Request-Id"}"#
+        );
+    }
+
+    #[test]
+    fn decode_sigma_response_compare() {
+        let s = Bytes::from_static(
+            b"0004001104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x108116978300",
+        );
+
+        let mut expected = SigmaResponse::new("0110", 4007040978, 8100).unwrap();
+        expected.fees.push(FeeData {
+            reason: 8116,
+            currency: Currency::Numeric(978),
+            amount: 300,
+        });
+
+        assert_eq!(SigmaResponse::decode(s).unwrap(), expected);
+    }
+
+    #[test]
+    fn decode_all_sigma_responses() {
+        let s = Bytes::from_static(
+            b"0002401104007040978T\x00\x31\x00\x00\x0484950002401104007040979T\x00\x31\x00\x00\x048100",
+        );
+
+        let resps = SigmaResponse::decode_all(s).unwrap();
+        assert_eq!(
+            resps,
+            vec![
+                SigmaResponse::new("0110", 4007040978, 8495).unwrap(),
+                SigmaResponse::new("0110", 4007040979, 8100).unwrap(),
+            ]
+        );
+
+        assert_eq!(SigmaResponse::decode_all(Bytes::new()), Ok(vec![]));
+    }
+
+    #[test]
+    fn decode_all_sigma_responses_partial() {
+        let s =
+            Bytes::from_static(b"0002401104007040978T\x00\x31\x00\x00\x0484950002401104007040979T");
+        assert_eq!(
+            SigmaResponse::decode_all(s),
+            Err(Error::IncorrectData(
+                "Trailing partial message after 1 decoded: 20 bytes left".into()
+            ))
+        );
+
+        let s = Bytes::from_static(b"0002401104007040978T\x00\x31\x00\x00\x048495000");
+        assert_eq!(
+            SigmaResponse::decode_all(s),
+            Err(Error::IncorrectData(
+                "Trailing partial message after 1 decoded: 3 bytes left".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn decode_all_sigma_requests() {
+        let s = Bytes::from_static(b"00016YM0200000000000100016NO01200000000002");
+
+        let reqs = SigmaRequest::decode_all(s).unwrap();
+        assert_eq!(
+            reqs,
+            vec![
+                SigmaRequest::new("Y", "M", "0200", 1).unwrap(),
+                SigmaRequest::new("N", "O", "0120", 2).unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn sigma_messages_try_from() {
+        const RESP: &[u8] = b"0002401104007040978T\x00\x31\x00\x00\x048495";
+        let expected = SigmaResponse::new("0110", 4007040978, 8495).unwrap();
+        assert_eq!(SigmaResponse::try_from(RESP), Ok(expected.clone()));
+        assert_eq!(
+            SigmaResponse::try_from(Bytes::from_static(RESP)),
+            Ok(expected)
+        );
+        assert!(SigmaResponse::try_from(&RESP[..10]).is_err());
+
+        const REQ: &[u8] = b"00016YM02000000000001";
+        let expected = SigmaRequest::new("Y", "M", "0200", 1).unwrap();
+        assert_eq!(SigmaRequest::try_from(REQ), Ok(expected.clone()));
+        assert_eq!(
+            SigmaRequest::try_from(Bytes::from_static(REQ)),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn sigma_messages_vec_roundtrip() {
+        const RESP: &[u8] = b"0002401104007040978T\x00\x31\x00\x00\x048495";
+        let resp = SigmaResponse::decode_slice(RESP).unwrap();
+        assert_eq!(resp.encode_vec().unwrap(), RESP.to_vec());
+
+        const REQ: &[u8] = b"00016YM02000000000001";
+        let req = SigmaRequest::decode_slice(REQ).unwrap();
+        assert_eq!(req.encode_vec().unwrap(), REQ.to_vec());
+        assert!(SigmaRequest::decode_slice(&REQ[..10]).is_err());
+    }
+
+    #[test]
+    fn decode_sigma_response_incorrect_auth_serno() {
+        let s = Bytes::from_static(b"000250110XYZ7040978T\x00\x31\x00\x00\x048100");
+
+        assert!(SigmaResponse::decode(s).is_err());
+    }
+
+    #[test]
+    fn decode_sigma_response_incorrect_reason() {
+        let s = Bytes::from_static(b"0002501104007040978T\x00\x31\x00\x00\x04ABCD");
+
+        assert!(SigmaResponse::decode(s).is_err());
+    }
+
+    #[test]
+    fn decode_sigma_response_fee_data() {
+        let s = Bytes::from_static(
+            b"0004001104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x108116978300",
+        );
+
+        let resp = SigmaResponse::decode(s).unwrap();
+        assert_eq!(resp.mti, "0110");
+        assert_eq!(resp.auth_serno, 4007040978);
+        assert_eq!(resp.reason, 8100);
+
+        let serialized = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"mti":"0110","auth_serno":4007040978,"reason":8100,"fees":[{"reason":8116,"currency":978,"amount":300}]}"#
+        );
+    }
+
+    #[test]
+    fn decode_sigma_response_multiple_fees() {
+        let s = Bytes::from_static(
+            b"0007201104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x108116978300T\x00\x32\x00\x00\x1181166439000T\x00\x32\x00\x00\x09811697850",
+        );
+
+        let resp = SigmaResponse::decode(s).unwrap();
+        assert_eq!(resp.fees.len(), 3);
+        assert_eq!(
+            resp.fees_by_currency(978).collect::<Vec<_>>(),
+            vec![
+                &FeeData {
+                    reason: 8116,
+                    currency: Currency::Numeric(978),
+                    amount: 300
+                },
+                &FeeData {
+                    reason: 8116,
+                    currency: Currency::Numeric(978),
+                    amount: 50
+                },
+            ]
+        );
+        assert_eq!(resp.total_amount(978), 350);
+        assert_eq!(resp.total_amount(643), 9000);
+        assert_eq!(resp.total_amount(840), 0);
+    }
+
+    #[test]
+    fn decode_sigma_response_correct_short_auth_serno() {
+        let s = Bytes::from_static(b"000240110123123    T\x00\x31\x00\x00\x048100");
+
+        let resp = SigmaResponse::decode(s).unwrap();
+        assert_eq!(resp.mti, "0110");
+        assert_eq!(resp.auth_serno, 123123);
+        assert_eq!(resp.reason, 8100);
+
+        let serialized = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"mti":"0110","auth_serno":123123,"reason":8100}"#
+        );
+    }
+
+    #[test]
+    fn decode_sigma_response_space_padded_auth_serno() {
+        let s = Bytes::from_static(b"000240110   123123 T\x00\x31\x00\x00\x048100");
+        assert_eq!(
+            SigmaResponse::decode(s).unwrap_err().to_string(),
+            "Incorrect field 'Serno', should be digits, optionally right-padded with spaces, got '   123123 '"
+        );
+
+        let s = Bytes::from_static(b"000240110123 123   T\x00\x31\x00\x00\x048100");
+        assert!(SigmaResponse::decode(s).is_err());
+
+        let s = Bytes::from_static(b"000240110          T\x00\x31\x00\x00\x048100");
+        assert!(SigmaResponse::decode(s).is_err());
+
+        let s = Bytes::from_static(b"00016YM0200   1      ");
+        assert!(SigmaRequest::decode(s).is_err());
+        let s = Bytes::from_static(b"00016YM02001         ");
+        assert_eq!(SigmaRequest::decode(s).unwrap().auth_serno, 1);
+    }
+
+    #[test]
+    fn decode_sigma_response_raw_bytes() {
+        const DATA: &[u8] = b"0003101104007040978T\x00\x33\x00\x00\x01XT\x00\x31\x00\x00\x048100";
+        let mut src = BytesMut::from(DATA);
+        src.extend_from_slice(b"00024");
+
+        let resp = SigmaResponse::decode(src.freeze()).unwrap();
+        assert_eq!(resp.raw_bytes().unwrap()[..], DATA[..]);
+        assert_eq!(resp.encode().unwrap()[..], DATA[..]);
+
+        let mut other = resp.clone();
+        other.raw = None;
+        assert_eq!(resp, other);
+        assert!(SigmaResponse::new("0110", 1, 0)
+            .unwrap()
+            .raw_bytes()
+            .is_none());
+    }
+
+    #[test]
+    fn decode_fee_data() {
+        let data = b"8116978300";
+
+        let fee = FeeData::from_slice(data).unwrap();
+        assert_eq!(fee.reason, 8116);
+        assert_eq!(fee.currency, 978);
+        assert_eq!(fee.amount, 300);
+    }
+
+    #[test]
+    fn decode_fee_data_short() {
+        assert_eq!(
+            FeeData::from_slice(b"8116978"),
+            Ok(FeeData {
+                reason: 8116,
+                currency: Currency::Numeric(978),
+                amount: 0
+            })
+        );
+        assert_eq!(FeeData::from_slice(b"81169785").unwrap().amount, 5);
+        assert_eq!(
+            FeeData::from_slice(b"811697"),
+            Err(Error::incorrect_field_data(
+                "FeeData",
+                "at least 7 bytes: 4 digit reason and 3 digit or 3 letter currency"
+            )
+            .got("811697"))
+        );
+    }
+
+    #[test]
+    fn fee_data_alpha_currency() {
+        let fee = FeeData::from_slice(b"8116USD300").unwrap();
+        assert_eq!(fee.currency, Currency::Alpha(*b"USD"));
+        assert_eq!(fee.amount, 300);
+        assert_eq!(fee.encode().unwrap()[..], b"8116USD300"[..]);
+        assert_eq!(
+            serde_json::to_string(&fee).unwrap(),
+            r#"{"reason":8116,"currency":"USD","amount":300}"#
+        );
+
+        assert!(FeeData::from_slice(b"8116US0300").is_err());
+        assert_eq!(
+            FeeData {
+                reason: 8116,
+                currency: Currency::Alpha(*b"U1D"),
+                amount: 300,
+            }
+            .encode(),
+            Err(Error::Bounds(
+                "FeeData.currency should be 3 ASCII letters".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn decode_fee_data_misaligned() {
+        assert_eq!(
+            FeeData::from_slice(b"+811978300"),
+            Err(Error::incorrect_field_data(
+                "FeeData",
+                "4 digit reason, 3 digit or 3 letter currency and amount digits"
+            )
+            .got("+811978300"))
+        );
+        // 3 digit reason "811" followed by "USD" shifts the currency into the amount
+        assert!(matches!(
+            FeeData::from_slice(b"811USD300"),
+            Err(Error::IncorrectFieldData { field_name, .. }) if field_name == "FeeData"
+        ));
+        assert!(FeeData::from_slice(b"8116978+300").is_err());
+        assert!(FeeData::from_slice(b"8116 97300").is_err());
+        assert!(FeeData::from_slice(b"811697A300").is_err());
+    }
+
+    #[test]
+    fn fee_data_for_request() {
+        let mut req = SigmaRequest::new("N", "X", "0100", 1).unwrap();
+        assert_eq!(
+            FeeData::for_request(&req, 8116, 300),
+            Err(Error::MissingField("i049".into()))
+        );
+
+        req.iso_fields.insert(49, "643".into());
+        assert_eq!(
+            FeeData::for_request(&req, 8116, 300).unwrap(),
+            FeeData {
+                reason: 8116,
+                currency: Currency::Numeric(643),
+                amount: 300,
+            }
+        );
+
+        req.iso_fields.insert(49, "RUB".into());
+        assert!(FeeData::for_request(&req, 8116, 300).is_err());
+        req.iso_fields.insert(49, "6430".into());
+        assert!(FeeData::for_request(&req, 8116, 300).is_err());
+    }
+
+    #[test]
+    fn parse_all_fee_data() {
+        assert_eq!(
+            FeeData::parse_all(b"81166430000000001008").unwrap_err(),
+            Error::IncorrectData(
+                "FeeData records should be 19 bytes long, 1 bytes left after the last one".into()
+            )
+        );
+
+        let fees = FeeData::parse_all(b"81166430000000001008117USD000000000020").unwrap();
+        assert_eq!(
+            fees,
+            vec![
+                FeeData {
+                    reason: 8116,
+                    currency: Currency::Numeric(643),
+                    amount: 100,
+                },
+                FeeData {
+                    reason: 8117,
+                    currency: Currency::Alpha(*b"USD"),
+                    amount: 20,
+                },
+            ]
+        );
+        assert!(FeeData::parse_all(b"").unwrap().is_empty());
+        assert!(FeeData::parse_all(b"8116643000000000100811797800000000002A").is_err());
+    }
+
+    #[test]
+    fn decode_fee_data_large_amount() {
+        let data = b"8116643123456789";
+
+        let fee = FeeData::from_slice(data).unwrap();
+        assert_eq!(fee.reason, 8116);
+        assert_eq!(fee.currency, 643);
+        assert_eq!(fee.amount, 123456789);
+    }
+
+    #[test]
+    fn decode_sigma_response_fee_data_additional_data() {
+        let s = Bytes::from_static(b"0015201104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x1181166439000T\x00\x48\x00\x01\x05CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI=");
+
+        let resp = SigmaResponse::decode(s).unwrap();
+        assert_eq!(resp.mti, "0110");
+        assert_eq!(resp.auth_serno, 4007040978);
+        assert_eq!(resp.reason, 8100);
+
+        let serialized = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"mti":"0110","auth_serno":4007040978,"reason":8100,"fees":[{"reason":8116,"currency":643,"amount":9000}],"adata":"CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI="}"#
+        );
+    }
+
+    #[test]
+    fn decode_sigma_response_fee_data_additional_data_supplementary_data() {
+        let s = Bytes::from_static(b"0016101104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x1181166439000T\x00\x48\x00\x01\x05CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI=T\x00\x50\x00\x00\x03123");
+
+        let resp = SigmaResponse::decode(s).unwrap();
+        assert_eq!(resp.mti, "0110");
+        assert_eq!(resp.auth_serno, 4007040978);
+        assert_eq!(resp.reason, 8100);
+        //"T0050": "123",
+        let serialized = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            serialized,
+            r#"{"mti":"0110","auth_serno":4007040978,"reason":8100,"fees":[{"reason":8116,"currency":643,"amount":9000}],"adata":"CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI=","supdata":"123"}"#
+        );
+    }
+
+    #[test]
+    fn encode_fee_data() {
+        let fee_data = FeeData {
+            reason: 8123,
+            currency: Currency::Numeric(643),
+            amount: 1234567890,
+        };
+
+        assert_eq!(fee_data.encode().unwrap()[..], b"81236431234567890"[..]);
+    }
+
+    #[test]
+    fn encode_fee_data_incorrect() {
+        assert!(FeeData {
+            reason: 10000,
+            currency: Currency::Numeric(643),
+            amount: 1234567890,
+        }
+        .encode()
+        .is_err());
+
+        assert!(FeeData {
+            reason: 8123,
+            currency: Currency::Numeric(6430),
+            amount: 1234567890,
+        }
+        .encode()
+        .is_err());
+    }
+
+    #[test]
+    fn encode_fee_data_checked() {
+        let fee_data = FeeData {
+            reason: 8123,
+            currency: Currency::Numeric(643),
+            amount: 1234567890,
+        };
+
+        assert_eq!(
+            fee_data.encode_checked(12).unwrap()[..],
+            b"81236431234567890"[..]
+        );
+        assert_eq!(
+            fee_data.encode_checked(10).unwrap()[..],
+            b"81236431234567890"[..]
+        );
+        assert!(matches!(fee_data.encode_checked(9), Err(Error::Bounds(_))));
+    }
+
+    #[test]
+    fn encode_sigma_response_fee_data_additional_data() {
+        let src = r#"{"mti":"0110","auth_serno":4007040978,"reason":8100,"fees":[{"reason":8116,"currency":643,"amount":9000}],"adata":"CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI="}"#;
+        let response = serde_json::from_str::<SigmaResponse>(src).unwrap();
+
+        let target = b"0015201104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x1181166439000T\x00\x48\x00\x01\x05CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI=";
+        assert_eq!(response.encode().unwrap()[..], target[..])
+    }
+
+    #[test]
+    fn decode_encode_sigma_response_fee_data_additional_data() {
+        const DATA: &[u8] = b"0015201104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x1181166439000T\x00\x48\x00\x01\x05CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI=";
+
+        let resp = SigmaResponse::decode(Bytes::from_static(DATA)).unwrap();
+        assert_eq!(resp.encode().unwrap()[..], DATA[..]);
+    }
+
+    #[test]
+    fn decode_encode_sigma_response_unknown_tags() {
+        const DATA: &[u8] = b"0005001104007040978T\x00\x31\x00\x00\x048100T\x00\x50\x00\x00\x03123T\x00\x77\x00\x00\x05IDDQDT\x00\x78\x00\x00\x00";
+
+        let resp = SigmaResponse::decode(Bytes::from_static(DATA)).unwrap();
+        assert_eq!(resp.supdata, Some("123".to_string()));
+        assert_eq!(resp.extra_tags.get(&77).unwrap(), "IDDQD");
+        assert_eq!(resp.extra_tags.get(&78).unwrap(), "");
+        assert!(resp.unparsed.is_empty());
+        assert_eq!(resp.encode().unwrap()[..], DATA[..]);
+
+        const ISO_DATA: &[u8] =
+            b"0004001104007040978T\x00\x31\x00\x00\x048100I\x00\x02\x00\x00\x0212T\x00\x05\x00\x00\x0299";
+        let resp = SigmaResponse::decode(Bytes::from_static(ISO_DATA)).unwrap();
+        assert_eq!(resp.extra_tags.get(&5).unwrap(), "99");
+        assert_eq!(resp.unparsed, b"I\x00\x02\x00\x00\x0212");
+        assert_eq!(resp.encode().unwrap()[..], ISO_DATA[..]);
+
+        let mut resp = resp;
+        resp.unparsed.clear();
+        assert_eq!(
+            resp.encode().unwrap()[..],
+            b"0003201104007040978T\x00\x31\x00\x00\x048100T\x00\x05\x00\x00\x0299"[..]
+        );
+    }
+
+    #[test]
+    fn encode_sigma_response_supdata() {
+        let mut resp = SigmaResponse::new("0110", 4007040978, 8100).unwrap();
+        resp.supdata = Some("123".into());
+        assert_eq!(
+            resp.encode().unwrap()[..],
+            b"0002401104007040978T\x00\x31\x00\x00\x048100"[..]
+        );
+
+        const DATA: &[u8] = b"0005001104007040978T\x00\x31\x00\x00\x048100T\x00\x50\x00\x00\x03123T\x00\x77\x00\x00\x05IDDQDT\x00\x78\x00\x00\x00";
+        let mut resp = SigmaResponse::decode(Bytes::from_static(DATA)).unwrap();
+        resp.supdata = Some("456".into());
+        assert_eq!(
+            resp.encode().unwrap()[..],
+            b"0004101104007040978T\x00\x31\x00\x00\x048100T\x00\x77\x00\x00\x05IDDQDT\x00\x78\x00\x00\x00"[..]
+        );
+    }
+
+    #[test]
+    fn decode_encode_sigma_response_keeps_field_order() {
+        // Unknown tag first, then fee with leading zeros in amount, adata before reason and no XRI
+        const DATA: &[u8] = b"0006001104007040978T\x00\x77\x00\x00\x05IDDQDT\x00\x32\x00\x00\x108116978030T\x00\x48\x00\x00\x03ABCT\x00\x31\x00\x00\x048100";
+        let resp = SigmaResponse::decode(Bytes::from_static(DATA)).unwrap();
+        assert_eq!(resp.fees[0].amount, 30);
+        assert_eq!(resp.encode().unwrap()[..], DATA[..]);
+
+        let mut modified = resp.clone();
+        modified.reason = 8495;
+        modified.xri = Some("X".into());
+        assert_eq!(
+            modified.encode().unwrap()[..],
+            b"0006701104007040978T\x00\x77\x00\x00\x05IDDQDT\x00\x32\x00\x00\x108116978030T\x00\x48\x00\x00\x03ABCT\x00\x31\x00\x00\x048495T\x00\x33\x00\x00\x01X"[..]
+        );
+
+        let mut modified = resp;
+        modified.fees[0].amount = 31;
+        modified.extra_tags.clear();
+        assert_eq!(
+            modified.encode().unwrap()[..],
+            b"0004801104007040978T\x00\x32\x00\x00\x09811697831T\x00\x48\x00\x00\x03ABCT\x00\x31\x00\x00\x048100"[..]
+        );
+    }
+
+    #[test]
+    fn decode_encode_sigma_response_without_reason() {
+        const DATA: &[u8] = b"0002101104007040978T\x00\x33\x00\x00\x01X";
+        let resp = SigmaResponse::decode(Bytes::from_static(DATA)).unwrap();
+        assert_eq!(resp.reason, 0);
+        assert_eq!(resp.encode().unwrap()[..], DATA[..]);
+
+        let mut modified = resp;
+        modified.reason = 8100;
+        assert_eq!(
+            modified.encode().unwrap()[..],
+            b"0003101104007040978T\x00\x33\x00\x00\x01XT\x00\x31\x00\x00\x048100"[..]
+        );
+    }
+
+    #[test]
+    fn decode_sigma_response_incorrect_field_offset() {
+        let s = Bytes::from_static(b"0002401104007040978T\x00\x31\x00\x00\x4A8495");
+
+        assert!(matches!(
+            SigmaResponse::decode(s),
+            Err(Error::DecodeAt { offset: 18, .. })
+        ));
+    }
+
+    #[test]
+    fn iso_field_data_from_ascii() {
+        assert_eq!(
+            IsoFieldData::from_ascii(Bytes::from_static(b"IDDQD Bank. GE")).unwrap(),
+            "IDDQD Bank. GE"
+        );
+        assert_eq!(
+            IsoFieldData::from_ascii(Bytes::from_static(b"0629\x7f1748")),
+            Err(Error::IncorrectData(
+                "Non-printable ASCII byte 0x7F at position 4".into()
+            ))
+        );
+        assert!(IsoFieldData::from_ascii(Bytes::from_static("Банк".as_bytes())).is_err());
+    }
+
+    #[test]
+    fn iso_field_data_split_delimited() {
+        let data = IsoFieldData::from("USRDT|2595100250");
+        let parts = data.split_delimited('|');
+        assert_eq!(parts, vec!["USRDT", "2595100250"]);
+        assert!(parts.iter().all(|p| matches!(p, Cow::Borrowed(_))));
 
-        if r.tags.get(&12).is_some() {
-            unreachable!();
-        }
+        let data = IsoFieldData::from_bytes(Bytes::from_static(b"A|B||"));
+        assert_eq!(data.split_delimited('|'), vec!["A", "B", "", ""]);
 
-        if r.tags.get(&13).is_some() {
-            unreachable!();
-        }
+        let data = IsoFieldData::from_bytes(Bytes::from_static(b"A|\xff"));
+        assert_eq!(data.split_delimited('|'), vec!["A", "\u{FFFD}"]);
+    }
 
-        assert_eq!(r.tags.get(&14).unwrap(), "IDDQD Bank");
+    #[test]
+    fn iso_field_data_parse_field48() {
+        let data = IsoFieldData::from("USRDT|2595100250");
+        let mut expected = BTreeMap::new();
+        expected.insert("USRDT".to_string(), "2595100250".to_string());
+        assert_eq!(data.parse_field48(), Ok(expected.clone()));
 
-        if r.tags.get(&15).is_some() {
-            unreachable!();
-        }
+        let data = IsoFieldData::from("USRDT|2595100250|CARD|");
+        expected.insert("CARD".to_string(), "".to_string());
+        assert_eq!(data.parse_field48(), Ok(expected));
 
-        assert_eq!(r.tags.get(&16).unwrap(), "74707182");
-        if r.tags.get(&17).is_some() {
-            unreachable!();
+        assert_eq!(
+            IsoFieldData::from("USRDT|2595100250|CARD").parse_field48(),
+            Err(
+                Error::incorrect_field_data("i048", "KEY|VALUE pairs separated by '|'")
+                    .got("USRDT|2595100250|CARD")
+            )
+        );
+    }
+
+    #[test]
+    fn iso_field_data_overpunch() {
+        for (src, v) in [
+            ("000010{", 100),
+            ("000010}", -100),
+            ("12A", 121),
+            ("12I", 129),
+            ("12J", -121),
+            ("12R", -129),
+            ("125", 125),
+            ("}", 0),
+        ] {
+            assert_eq!(IsoFieldData::String(src.into()).parse_overpunch(), Ok(v));
         }
-        assert_eq!(r.tags.get(&18).unwrap(), "Y");
-        assert_eq!(r.tags.get(&22).unwrap(), "000000000010");
-        assert_eq!(r.tags.get(&23).unwrap(), "X-Request-Id");
 
-        assert_eq!(r.iso_fields.get(&0).unwrap(), "0100");
+        assert!(IsoFieldData::String("".into()).parse_overpunch().is_err());
+        assert!(IsoFieldData::String("12S".into())
+            .parse_overpunch()
+            .is_err());
+        assert!(IsoFieldData::String("1 2A".into())
+            .parse_overpunch()
+            .is_err());
+        assert!(IsoFieldData::String("99999999999999999999}".into())
+            .parse_overpunch()
+            .is_err());
+    }
 
-        if r.iso_fields.get(&1).is_some() {
-            unreachable!();
+    #[test]
+    fn iso_field_data_into_bytes() {
+        assert_eq!(IsoFieldData::String("ABC".into()).into_bytes(), b"ABC");
+        assert_eq!(
+            IsoFieldData::Raw(vec![0xff, 0x01]).into_bytes(),
+            [0xff, 0x01]
+        );
+        assert_eq!(
+            IsoFieldData::Bytes(Bytes::from_static(b"\x00A")).into_bytes(),
+            b"\x00A"
+        );
+    }
+
+    #[test]
+    fn iso_field_data_serde() {
+        let string = IsoFieldData::from("USRDT|2595100250");
+        let raw = IsoFieldData::from(&b"\x9f\x02\x06"[..]);
+        let bytes = IsoFieldData::Bytes(Bytes::from_static(b"0629"));
+
+        assert_eq!(
+            serde_json::to_string(&string).unwrap(),
+            r#""USRDT|2595100250""#
+        );
+        assert_eq!(serde_json::to_string(&raw).unwrap(), r#"{"raw":"nwIG"}"#);
+        assert_eq!(serde_json::to_string(&bytes).unwrap(), r#""0629""#);
+
+        for v in [string, raw, bytes] {
+            let serialized = serde_json::to_string(&v).unwrap();
+            assert_eq!(
+                serde_json::from_str::<IsoFieldData>(&serialized).unwrap(),
+                v
+            );
         }
 
-        assert_eq!(r.iso_fields.get(&2).unwrap(), "555544******1111");
-        assert_eq!(r.iso_fields.get(&3).unwrap(), "500000");
-        assert_eq!(r.iso_fields.get(&4).unwrap(), "000100000000");
-        assert_eq!(r.iso_fields.get(&6).unwrap(), "000100000000");
-        assert_eq!(r.iso_fields.get(&7).unwrap(), "0629151748");
-        assert_eq!(r.iso_fields.get(&11).unwrap(), "100250");
-        assert_eq!(r.iso_fields.get(&12).unwrap(), "181748");
-        assert_eq!(r.iso_fields.get(&13).unwrap(), "0629");
-        assert_eq!(r.iso_fields.get(&18).unwrap(), "0000");
-        assert_eq!(r.iso_fields.get(&22).unwrap(), "0000");
-        assert_eq!(r.iso_fields.get(&25).unwrap(), "02");
-        assert_eq!(r.iso_fields.get(&32).unwrap(), "010455");
-        assert_eq!(r.iso_fields.get(&37).unwrap(), "002595100250");
-        assert_eq!(r.iso_fields.get(&41).unwrap(), "990");
-        assert_eq!(r.iso_fields.get(&42).unwrap(), "DCZ1");
+        assert!(matches!(
+            serde_json::from_str::<IsoFieldData>(r#"{"raw":"nwIG"}"#).unwrap(),
+            IsoFieldData::Raw(_)
+        ));
+        assert!(serde_json::from_str::<IsoFieldData>(r#"{"raw":"!!"}"#).is_err());
+        assert!(serde_json::from_str::<IsoFieldData>("123").is_err());
+    }
+
+    #[test]
+    fn iso_field_data_bcd() {
         assert_eq!(
-            r.iso_fields.get(&43).unwrap(),
-            "IDDQD Bank.                         GE"
+            IsoFieldData::from(&b"\x00\x06\x43"[..]).as_bcd_u64(),
+            Ok(643)
         );
-        assert_eq!(r.iso_fields.get(&48).unwrap(), "USRDT|2595100250");
-        assert_eq!(r.iso_fields.get(&49).unwrap(), "643");
-        assert_eq!(r.iso_fields.get(&51).unwrap(), "643");
-        assert_eq!(r.iso_fields.get(&60).unwrap(), "3");
-        assert_eq!(r.iso_fields.get(&101).unwrap(), "91926242");
-        assert_eq!(r.iso_fields.get(&102).unwrap(), "2371492071643");
+        assert_eq!(IsoFieldData::from(&b""[..]).as_bcd_u64(), Ok(0));
+        assert!(IsoFieldData::from(&b"\x06\x4A"[..]).as_bcd_u64().is_err());
+        assert!(IsoFieldData::from(&[0x99u8; 10][..]).as_bcd_u64().is_err());
+
+        assert_eq!(IsoFieldData::from_bcd(643, 3).unwrap(), b"\x06\x43");
+        assert_eq!(IsoFieldData::from_bcd(643, 6).unwrap(), b"\x00\x06\x43");
+        assert_eq!(
+            IsoFieldData::from_bcd(100000000000, 12)
+                .unwrap()
+                .as_bcd_u64(),
+            Ok(100000000000)
+        );
+        assert!(IsoFieldData::from_bcd(6430, 3).is_err());
+
+        let empty = IsoFieldData::from_bcd(0, 0).unwrap();
+        assert_eq!(empty, b"");
+        assert_eq!(empty.as_bcd_u64(), Ok(0));
+        assert_eq!(IsoFieldData::from_bcd(0, 1).unwrap(), b"\x00");
+        assert!(IsoFieldData::from_bcd(1, 0).is_err());
     }
 
     #[test]
-    fn missing_saf() {
-        let payload = r#"{
-            "SRC": "M",
-            "MTI": "0200"
-        }"#;
+    fn set_auth_serno_checked() {
+        let mut req = SigmaRequest::new("Y", "M", "0100", 1).unwrap();
+        req.set_auth_serno(MAX_AUTH_SERNO).unwrap();
+        assert_eq!(req.auth_serno, MAX_AUTH_SERNO);
+        assert_eq!(
+            req.set_auth_serno(MAX_AUTH_SERNO + 1).unwrap_err(),
+            Error::Bounds("auth_serno '10000000000' contains more than 10 digits".into())
+        );
+        assert_eq!(req.auth_serno, MAX_AUTH_SERNO);
 
-        if SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).is_ok() {
-            unreachable!("Should not return Ok if mandatory field is missing");
-        }
+        let mut resp = SigmaResponse::new("0110", 1, 8100).unwrap();
+        resp.set_auth_serno(4007040978).unwrap();
+        assert_eq!(resp.auth_serno, 4007040978);
+        assert!(resp.set_auth_serno(u64::MAX).is_err());
+        assert_eq!(resp.auth_serno, 4007040978);
+
+        resp.auth_serno = MAX_AUTH_SERNO + 1;
+        assert_eq!(
+            resp.encode().unwrap_err(),
+            Error::Bounds("auth_serno '10000000000' contains more than 10 digits".into())
+        );
     }
 
     #[test]
-    fn invalid_saf() {
-        let payload = r#"{
-        	"SAF": 1234,
-            "SRC": "M",
-            "MTI": "0200"
-        }"#;
+    fn display_summary() {
+        let mut req = SigmaRequest::new("Y", "M", "0200", 6007040979).unwrap();
+        req.set_tag(18, "0");
+        req.set_iso_field(2, "5555444433332222");
+        req.set_iso_field(4, "100");
+        req.set_iso_subfield(48, 1, "X");
+        assert_eq!(
+            req.to_string(),
+            "SigmaRequest[MTI=0200 SRC=M SAF=Y serno=6007040979 tags=1 iso_fields=2 iso_subfields=1]"
+        );
 
-        if SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).is_ok() {
-            unreachable!("Should not return Ok if the filed has invalid format");
-        }
+        let mut resp = SigmaResponse::new("0210", 6007040979, 8100).unwrap();
+        resp.fees.push(FeeData::from_slice(b"8116978300").unwrap());
+        assert_eq!(
+            resp.to_string(),
+            "SigmaResponse[MTI=0210 serno=6007040979 reason=8100 fees=1]"
+        );
     }
 
     #[test]
-    fn missing_source() {
-        let payload = r#"{
-        	"SAF": "N",
-            "MTI": "0200"
-        }"#;
+    fn decode_padded() {
+        let options = DecodeOptions {
+            allow_padding: true,
+            ..Default::default()
+        };
 
-        if SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).is_ok() {
-            unreachable!("Should not return Ok if mandatory field is missing");
-        }
+        let data = Bytes::from_static(b"00027YM02000000000001T\x00\x18\x00\x00\x010    ");
+        assert!(SigmaRequest::decode(data.clone()).is_err());
+        let req = SigmaRequest::decode_with_options(data, options).unwrap();
+        assert_eq!(req.tag(18), Some("0"));
+        assert_eq!(req.tags.len(), 1);
+
+        let data = Bytes::from_static(b"0002701104007040978T\x00\x31\x00\x00\x048495\x00 \x00");
+        assert!(SigmaResponse::decode(data.clone()).is_err());
+        let resp = SigmaResponse::decode_with_options(data, options).unwrap();
+        assert_eq!(resp.reason, 8495);
+        assert!(resp.unparsed.is_empty());
+
+        // Padding is only recognized at the end of the message
+        let data = Bytes::from_static(b"00026YM02000000000001  T\x00\x18\x00\x00\x010 ");
+        assert!(SigmaRequest::decode_with_options(data, options).is_err());
     }
 
     #[test]
-    fn invalid_source() {
-        let payload = r#"{
-        	"SAF": "N",
-            "SRC": 929292,
-            "MTI": "0200"
-        }"#;
+    fn len_width_roundtrip() {
+        let mut req = SigmaRequest::new("Y", "M", "0200", 1).unwrap();
+        req.set_tag(18, "0");
+        let resp = SigmaResponse::new("0210", 4007040978, 8495).unwrap();
 
-        if SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).is_ok() {
-            unreachable!("Should not return Ok if the filed has invalid format");
-        }
+        let encoded = req.encode_with_len_width(4).unwrap();
+        assert_eq!(encoded, b"0023YM02000000000001T\x00\x18\x00\x00\x010"[..]);
+        assert_eq!(
+            SigmaRequest::decode_with_len_width(encoded, 4).unwrap(),
+            req
+        );
+        let encoded = resp.encode_with_len_width(4).unwrap();
+        assert_eq!(encoded, b"002402104007040978T\x00\x31\x00\x00\x048495"[..]);
+        assert_eq!(
+            SigmaResponse::decode_with_len_width(encoded, 4).unwrap(),
+            resp
+        );
+
+        let encoded = req.encode_with_len_width(6).unwrap();
+        assert_eq!(encoded, b"000023YM02000000000001T\x00\x18\x00\x00\x010"[..]);
+        assert_eq!(
+            SigmaRequest::decode_with_len_width(encoded, 6).unwrap(),
+            req
+        );
+        let encoded = resp.encode_with_len_width(6).unwrap();
+        assert_eq!(
+            encoded,
+            b"00002402104007040978T\x00\x31\x00\x00\x048495"[..]
+        );
+        assert_eq!(
+            SigmaResponse::decode_with_len_width(encoded, 6).unwrap(),
+            resp
+        );
+
+        req.set_tag(48, "A".repeat(9999));
+        assert_eq!(
+            req.encode_with_len_width(4),
+            Err(Error::Bounds(
+                "Message is 10028 bytes long, should be at most 9999".into()
+            ))
+        );
+        assert!(req.encode_with_len_width(6).is_ok());
     }
 
     #[test]
-    fn missing_mti() {
-        let payload = r#"{
-        	"SAF": "N",
-        	"SRC": "O"
-        }"#;
+    fn sigma_response_binary_adata() {
+        let src = Bytes::from_static(b"0002301104007040978T\x00\x48\x00\x00\x03\xff\x00\x80");
+        let resp = SigmaResponse::decode(src.clone()).unwrap();
+        assert_eq!(resp.adata.as_ref().unwrap(), &b"\xff\x00\x80"[..]);
+        assert_eq!(resp.encode().unwrap(), src);
 
-        if SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).is_ok() {
-            unreachable!("Should not return Ok if mandatory field is missing");
-        }
+        let json = serde_json::to_string(&resp).unwrap();
+        assert_eq!(
+            json,
+            r#"{"mti":"0110","auth_serno":4007040978,"reason":0,"adata":{"raw":"/wCA"}}"#
+        );
+        assert_eq!(serde_json::from_str::<SigmaResponse>(&json).unwrap(), resp);
     }
 
     #[test]
-    fn invalid_mti() {
-        let payload = r#"{
-        	"SAF": "N",
-            "SRC": "O",
-            "MTI": 1200
-        }"#;
+    #[cfg(feature = "std")]
+    fn decode_random_data_does_not_panic() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
 
-        if SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).is_ok() {
-            unreachable!("Should not return Ok if the filed has invalid format");
+        const SAMPLES: &[&[u8]] = &[
+            b"00042YM02000000000001T\x00\x18\x00\x00\x010I\x00\x02\x00\x00\x045555S\x00\x48\x01\x00\x03abc",
+            b"0004001104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x108116978300",
+        ];
+
+        SigmaRequest::decode(Bytes::from_static(SAMPLES[0])).unwrap();
+        SigmaResponse::decode(Bytes::from_static(SAMPLES[1])).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(0x5167_4d41);
+        for _ in 0..10000 {
+            let len = rng.gen_range(0..64);
+            let mut data: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+            if len >= LENGTH_BYTES_COUNT && rng.gen_bool(0.5) {
+                let prefix = format!("{:05}", len - LENGTH_BYTES_COUNT);
+                data[..LENGTH_BYTES_COUNT].copy_from_slice(prefix.as_bytes());
+            }
+            let _ = SigmaRequest::decode(data.clone().into());
+            let _ = SigmaResponse::decode(data.into());
+
+            // Corrupt bytes of a valid message to get deeper into field parsing
+            let mut data = SAMPLES[rng.gen_range(0..SAMPLES.len())].to_vec();
+            for _ in 0..rng.gen_range(1..4) {
+                let i = rng.gen_range(LENGTH_BYTES_COUNT..data.len());
+                data[i] = rng.gen();
+            }
+            let _ = SigmaRequest::decode(data.clone().into());
+            let _ = SigmaResponse::decode(data.into());
         }
     }
 
     #[test]
-    fn generating_auth_serno() {
-        let payload = r#"{
-                "SAF": "Y",
-                "SRC": "M",
-                "MTI": "0200",
-                "T0000": "02371492071643"
-            }"#;
+    fn decode_rejecting_trailing_bytes() {
+        let options = DecodeOptions {
+            reject_trailing_bytes: true,
+            ..Default::default()
+        };
 
-        let r: SigmaRequest =
-            SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
-        assert!(
-            r.auth_serno > 0,
-            "Should generate authorization serno if the field is missing"
+        let data = Bytes::from_static(b"00016YM02000000000001T\x00\x18\x00\x00\x010");
+        assert_eq!(SigmaRequest::decode(data.clone()).unwrap().tags.len(), 0);
+        assert_eq!(
+            SigmaRequest::decode_with_options(data, options),
+            Err(Error::IncorrectData(
+                "Declared message length 16 doesn't match the input: 7 bytes left after the message"
+                    .into()
+            ))
         );
+        assert!(SigmaRequest::decode_with_options(
+            Bytes::from_static(b"00023YM02000000000001T\x00\x18\x00\x00\x010"),
+            options
+        )
+        .is_ok());
+
+        let data = Bytes::from_static(b"0002401104007040978T\x00\x31\x00\x00\x048495 ");
+        assert!(SigmaResponse::decode(data.clone()).is_ok());
+        assert!(SigmaResponse::decode_with_options(data, options).is_err());
     }
 
     #[test]
-    fn encode_generated_auth_serno() {
-        let payload = r#"{
-                "SAF": "Y",
-                "SRC": "M",
-                "MTI": "0201",
-                "Serno": 7877706965687192023
-            }"#;
+    fn sigma_response_sort_fees() {
+        let fees = [
+            FeeData {
+                reason: 8116,
+                currency: Currency::Numeric(978),
+                amount: 100,
+            },
+            FeeData {
+                reason: 8116,
+                currency: Currency::Numeric(643),
+                amount: 9000,
+            },
+            FeeData {
+                reason: 8114,
+                currency: Currency::Numeric(643),
+                amount: 5,
+            },
+        ];
+
+        let mut first = SigmaResponse::new("0110", 1, 8100).unwrap();
+        first.fees.extend(fees.iter().cloned());
+        first.sort_fees();
+        let mut second = SigmaResponse::new("0110", 1, 8100).unwrap();
+        second.fees.extend(fees.iter().rev().cloned());
+        second.sort_fees();
 
-        let r: SigmaRequest =
-            SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
-        let serialized = r.encode().unwrap();
         assert_eq!(
-            serialized,
-            b"00016YM02017877706965"[..],
-            "Original auth serno should be trimmed to 10 bytes"
+            first
+                .fees
+                .iter()
+                .map(|f| (f.currency, f.reason))
+                .collect::<Vec<_>>(),
+            vec![
+                (Currency::Numeric(643), 8114),
+                (Currency::Numeric(643), 8116),
+                (Currency::Numeric(978), 8116)
+            ]
         );
+        assert_eq!(first.encode().unwrap(), second.encode().unwrap());
     }
 
     #[test]
-    fn encode_sigma_request() {
-        let payload = r#"{
-                "SAF": "Y",
-                "SRC": "M",
-                "MTI": "0200",
-                "Serno": 6007040979,
-                "T0000": 2371492071643,
-                "T0001": "C",
-                "T0002": 643,
-                "T0003": "000100000000",
-                "T0004": 978,
-                "T0005": "000300000000",
-                "T0006": "OPS6",
-                "T0007": 19,
-                "T0008": 643,
-                "T0009": 3102,
-                "T0010": 3104,
-                "T0011": 2,
-                "T0014": "IDDQD Bank",
-                "T0016": 74707182,
-                "T0018": "Y",
-                "T0022": "000000000010",
-                "i000": "0100",
-                "i002": "555544******1111",
-                "i003": "500000",
-                "i004": "000100000000",
-                "i006": "000100000000",
-                "i007": "0629151748",
-                "i011": "100250",
-                "i012": "181748",
-                "i013": "0629",
-                "i018": "0000",
-                "i022": "0000",
-                "i025": "02",
-                "i032": "010455",
-                "i037": "002595100250",
-                "i041": 990,
-                "i042": "DCZ1",
-                "i043": "IDDQD Bank.                         GE",
-                "i048": "USRDT|2595100250",
-                "i049": 643,
-                "i051": 643,
-                "i060": 3,
-                "i101": 91926242,
-                "i102": 2371492071643
-            }"#;
+    fn sigma_response_for_request() {
+        let req = SigmaRequest::new("N", "M", "0200", 6007040979).unwrap();
+        let resp = SigmaResponse::for_request(&req, 8100).unwrap();
+        assert_eq!(resp.mti(), "0210");
+        assert_eq!(resp.auth_serno, 6007040979);
+        assert_eq!(resp.reason, 8100);
 
-        let r: SigmaRequest =
-            SigmaRequest::from_json_value(serde_json::from_str(payload).unwrap()).unwrap();
-        let serialized = r.encode().unwrap();
+        let req = SigmaRequest::new("Y", "M", "0120", 1).unwrap();
+        assert_eq!(
+            SigmaResponse::for_request(&req, 8100).unwrap().mti(),
+            "0130"
+        );
+        let req = SigmaRequest::new("Y", "M", "0121", 1).unwrap();
+        assert_eq!(
+            SigmaResponse::for_request(&req, 8100).unwrap().mti(),
+            "0130"
+        );
+
+        let req = SigmaRequest::new("N", "M", "0240", 1).unwrap();
         assert_eq!(
-            serialized,
-            b"00536YM02006007040979T\x00\x00\x00\x00\x132371492071643T\x00\x01\x00\x00\x01CT\x00\x02\x00\x00\x03643T\x00\x03\x00\x00\x12000100000000T\x00\x04\x00\x00\x03978T\x00\x05\x00\x00\x12000300000000T\x00\x06\x00\x00\x04OPS6T\x00\x07\x00\x00\x0219T\x00\x08\x00\x00\x03643T\x00\t\x00\x00\x043102T\x00\x10\x00\x00\x043104T\x00\x11\x00\x00\x012T\x00\x14\x00\x00\x10IDDQD BankT\x00\x16\x00\x00\x0874707182T\x00\x18\x00\x00\x01YT\x00\x22\x00\x00\x12000000000010I\x00\x00\x00\x00\x040100I\x00\x02\x00\x00\x16555544******1111I\x00\x03\x00\x00\x06500000I\x00\x04\x00\x00\x12000100000000I\x00\x06\x00\x00\x12000100000000I\x00\x07\x00\x00\x100629151748I\x00\x11\x00\x00\x06100250I\x00\x12\x00\x00\x06181748I\x00\x13\x00\x00\x040629I\x00\x18\x00\x00\x040000I\x00\"\x00\x00\x040000I\x00%\x00\x00\x0202I\x002\x00\x00\x06010455I\x007\x00\x00\x12002595100250I\x00A\x00\x00\x03990I\x00B\x00\x00\x04DCZ1I\x00C\x00\x008IDDQD Bank.                         GEI\x00H\x00\x00\x16USRDT|2595100250I\x00I\x00\x00\x03643I\x00Q\x00\x00\x03643I\x00`\x00\x00\x013I\x01\x01\x00\x00\x0891926242I\x01\x02\x00\x00\x132371492071643"[..]
+            SigmaResponse::for_request(&req, 8100),
+            Err(Error::IncorrectData(
+                "MTI 0240 has no defined response".into()
+            ))
         );
+        let req = SigmaRequest::new("N", "M", "0210", 1).unwrap();
+        assert!(SigmaResponse::for_request(&req, 8100).is_err());
     }
 
     #[test]
-    fn decode_sigma_request() {
-        let src = Bytes::from_static(b"00545YM02006007040979T\x00\x00\x00\x00\x132371492071643T\x00\x01\x00\x00\x01CT\x00\x02\x00\x00\x03643T\x00\x03\x00\x00\x12000100000000T\x00\x04\x00\x00\x03978T\x00\x05\x00\x00\x12000300000000T\x00\x06\x00\x00\x04OPS6T\x00\x07\x00\x00\x0219T\x00\x08\x00\x00\x03643T\x00\t\x00\x00\x043102T\x00\x10\x00\x00\x043104T\x00\x11\x00\x00\x012T\x00\x14\x00\x00\x10IDDQD BankT\x00\x16\x00\x00\x0874707182T\x00\x18\x00\x00\x01YT\x00\x22\x00\x00\x12000000000010T\x00\x50\x00\x00\x03123I\x00\x00\x00\x00\x040100I\x00\x02\x00\x00\x16555544******1111I\x00\x03\x00\x00\x06500000I\x00\x04\x00\x00\x12000100000000I\x00\x06\x00\x00\x12000100000000I\x00\x07\x00\x00\x100629151748I\x00\x11\x00\x00\x06100250I\x00\x12\x00\x00\x06181748I\x00\x13\x00\x00\x040629I\x00\x18\x00\x00\x040000I\x00\"\x00\x00\x040000I\x00%\x00\x00\x0202I\x002\x00\x00\x06010455I\x007\x00\x00\x12002595100250I\x00A\x00\x00\x03990I\x00B\x00\x00\x04DCZ1I\x00C\x00\x008IDDQD Bank.                         GEI\x00H\x00\x00\x16USRDT|2595100250I\x00I\x00\x00\x03643I\x00Q\x00\x00\x03643I\x00`\x00\x00\x013I\x01\x01\x00\x00\x0891926242I\x01\x02\x00\x00\x132371492071643");
-        let json = r#"{
-                "SAF": "Y",
-                "SRC": "M",
-                "MTI": "0200",
-                "Serno": 6007040979,
-                "T0000": 2371492071643,
-                "T0001": "C",
-                "T0002": 643,
-                "T0003": "000100000000",
-                "T0004": 978,
-                "T0005": "000300000000",
-                "T0006": "OPS6",
-                "T0007": 19,
-                "T0008": 643,
-                "T0009": 3102,
-                "T0010": 3104,
-                "T0011": 2,
-                "T0014": "IDDQD Bank",
-                "T0016": 74707182,
-                "T0018": "Y",
-                "T0022": "000000000010",
-                "T0050": "123",
-                "i000": "0100",
-                "i002": "555544******1111",
-                "i003": "500000",
-                "i004": "000100000000",
-                "i006": "000100000000",
-                "i007": "0629151748",
-                "i011": "100250",
-                "i012": "181748",
-                "i013": "0629",
-                "i018": "0000",
-                "i022": "0000",
-                "i025": "02",
-                "i032": "010455",
-                "i037": "002595100250",
-                "i041": 990,
-                "i042": "DCZ1",
-                "i043": "IDDQD Bank.                         GE",
-                "i048": "USRDT|2595100250",
-                "i049": 643,
-                "i051": 643,
-                "i060": 3,
-                "i101": 91926242,
-                "i102": 2371492071643
-            }"#;
+    fn sigma_request_field_presence() {
+        let mut r = SigmaRequest::new("N", "M", "0200", 1).unwrap();
+        r.set_tag(18, "Y");
+        r.set_tag(6, "OPS6");
+        r.set_iso_field(0, "0100");
+        r.set_iso_field(3, "500000");
+        r.set_iso_field(2, "555544******1111");
+
+        assert!(r.has_tag(6));
+        assert!(!r.has_tag(7));
+        assert_eq!(r.present_tags(), vec![6, 18]);
+        assert!(r.has_iso_field(2));
+        assert!(!r.has_iso_field(4));
+        assert_eq!(r.present_iso_fields(), vec![0, 2, 3]);
+
+        let mut expected = [0u8; 16];
+        expected[0] = 0x60;
+        assert_eq!(r.to_iso_bitmap(), expected);
+
+        r.set_iso_field(8, "X");
+        r.set_iso_field(128, "X");
+        r.set_iso_field(130, "X");
+        expected[0] = 0xE1;
+        expected[15] = 0x01;
+        assert_eq!(r.to_iso_bitmap(), expected);
+
+        r.set_iso_field(101, "X");
+        r.set_iso_field(1000, "X");
+        expected[12] = 0x08;
+        assert_eq!(r.to_iso_bitmap_checked(), (expected, vec![130, 1000]));
+    }
 
-        let target: SigmaRequest =
-            SigmaRequest::from_json_value(serde_json::from_str(json).unwrap()).unwrap();
+    #[test]
+    fn sigma_request_iso_subfield_group() {
+        let mut r = SigmaRequest::new("N", "M", "0200", 1).unwrap();
+        r.set_iso_subfield(48, 2, "B");
+        r.set_iso_subfield(48, 1, "A");
+        r.set_iso_subfield(60, 1, "C");
+        r.set_iso_subfield(47, 255, "D");
+
+        let group = r.iso_subfield_group(48);
+        assert_eq!(group.keys().copied().collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(group[&1], "A");
+        assert_eq!(group[&2], "B");
+        assert!(r.iso_subfield_group(61).is_empty());
+    }
 
-        let req = SigmaRequest::decode(src).unwrap();
+    #[test]
+    fn decode_rejecting_duplicate_tags() {
+        let options = DecodeOptions {
+            reject_duplicate_tags: true,
+            ..Default::default()
+        };
 
-        assert_eq!(req, target);
+        let data = Bytes::from_static(
+            b"00030YM02000000000001T\x00\x18\x00\x00\x01YT\x00\x18\x00\x00\x01N",
+        );
+        assert_eq!(
+            SigmaRequest::decode(data.clone()).unwrap().tag(18),
+            Some("N")
+        );
+        assert_eq!(
+            SigmaRequest::decode_with_options(data, options),
+            Err(Error::IncorrectData(
+                "Duplicate tag T0018 at byte 23 of message body".into()
+            ))
+        );
+
+        let data = Bytes::from_static(
+            b"00038YM02000000000001I\x00\x02\x00\x00\x0212S\x00\x48\x01\x00\x011S\x00\x48\x02\x00\x012",
+        );
+        assert!(SigmaRequest::decode_with_options(data, options).is_ok());
+
+        let data = Bytes::from_static(
+            b"0005201104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x0881169780T\x00\x32\x00\x00\x0881169785",
+        );
+        assert_eq!(
+            SigmaResponse::decode_with_options(data, options)
+                .unwrap()
+                .fees
+                .len(),
+            2
+        );
+
+        let data = Bytes::from_static(
+            b"0003401104007040978T\x00\x31\x00\x00\x048100T\x00\x31\x00\x00\x048495",
+        );
+        assert_eq!(SigmaResponse::decode(data.clone()).unwrap().reason, 8495);
+        assert!(SigmaResponse::decode_with_options(data, options).is_err());
     }
 
     #[test]
-    fn decode_sigma_response() {
-        let s = Bytes::from_static(b"0002401104007040978T\x00\x31\x00\x00\x048495");
+    fn header_layout() {
+        let req = SigmaRequest::new("Y", "M", "0200", 1).unwrap();
+        assert_eq!(
+            req.encode().unwrap().len(),
+            LENGTH_BYTES_COUNT + REQUEST_HEADER_LEN
+        );
+        let resp = SigmaResponse::new("0210", 1, 8100).unwrap();
+        assert_eq!(
+            resp.encode().unwrap().len(),
+            LENGTH_BYTES_COUNT + RESPONSE_HEADER_LEN + FIELD_HEADER_LEN + 4
+        );
+    }
 
-        let resp = SigmaResponse::decode(s).unwrap();
-        assert_eq!(resp.mti, "0110");
-        assert_eq!(resp.auth_serno, 4007040978);
-        assert_eq!(resp.reason, 8495);
+    #[test]
+    fn sigma_request_diff() {
+        let mut old = SigmaRequest::new("N", "M", "0200", 1).unwrap();
+        old.set_tag(6, "OPS6");
+        old.set_tag(18, "Y");
+        old.set_iso_field(2, "555544******1111");
+        old.set_iso_subfield(48, 1, "A");
+
+        let mut new = old.clone();
+        new.auth_serno = 2;
+        assert!(old.diff(&new).is_empty());
+
+        new.set_tag(18, "N");
+        new.remove_tag(6);
+        new.set_iso_subfield(48, 2, "B");
+        new.set_iso_field(3, "500000");
 
-        let serialized = serde_json::to_string(&resp).unwrap();
         assert_eq!(
-            serialized,
-            r#"{"mti":"0110","auth_serno":4007040978,"reason":8495}"#
+            old.diff(&new),
+            vec![
+                FieldDiff::Removed {
+                    tag: Tag::Regular(6),
+                    value: "OPS6".into()
+                },
+                FieldDiff::Changed {
+                    tag: Tag::Regular(18),
+                    old: "Y".into(),
+                    new: "N".into()
+                },
+                FieldDiff::Added {
+                    tag: Tag::Iso(3),
+                    value: "500000".into()
+                },
+                FieldDiff::Added {
+                    tag: Tag::IsoSubfield(48, 2),
+                    value: "B".into()
+                },
+            ]
         );
     }
 
     #[test]
-    fn decode_sigma_response_xri() {
-        let s = Bytes::from_static(
-            b"0004201104007040978T\x00\x31\x00\x00\x048495T\x00\x33\x00\x00\x12X-Request-Id",
+    fn sigma_request_mark_forwarded() {
+        let mut r = SigmaRequest::new("Y", "M", "0200", 1).unwrap();
+        assert!(r.is_saf());
+        r.mark_forwarded();
+        assert!(!r.is_saf());
+        assert_eq!(r.saf(), Saf::No);
+        assert_eq!(r.encode().unwrap()[..], b"00016NM02000000000001"[..]);
+    }
+
+    #[test]
+    fn decode_sigma_response_requiring_reason() {
+        let options = DecodeOptions {
+            require_reason: true,
+            ..Default::default()
+        };
+
+        let data = Bytes::from_static(b"0001401104007040978");
+        assert_eq!(SigmaResponse::decode(data.clone()).unwrap().reason, 0);
+        assert_eq!(
+            SigmaResponse::decode_with_options(data, options),
+            Err(Error::MissingField("reason".into()))
         );
 
-        let resp = SigmaResponse::decode(s).unwrap();
-        assert_eq!(resp.mti, "0110");
+        let data = Bytes::from_static(b"0002101104007040978T\x00\x31\x00\x00\x010");
+        assert_eq!(
+            SigmaResponse::decode_with_options(data, options)
+                .unwrap()
+                .reason,
+            0
+        );
+    }
+
+    #[test]
+    fn json_iso_field_above_999() {
+        let mut req = SigmaRequest::new("N", "X", "0100", 1).unwrap();
+        req.iso_fields.insert(1000, "X".into());
+        req.iso_fields.insert(999, "Y".into());
+
+        let json = req.to_json_value();
+        assert_eq!(json["i1000"], "X");
+        assert_eq!(json["i999"], "Y");
+        assert_eq!(SigmaRequest::from_json_value(json).unwrap(), req);
+
+        assert!(SigmaRequest::from_json_value(
+            serde_json::json!({"SAF": "N", "SRC": "X", "MTI": "0100", "Serno": 1, "i10000": "X"})
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn decode_one_returns_rest() {
+        let resp = SigmaResponse::new("0110", 4007040978, 8100).unwrap();
+        let mut data = BytesMut::new();
+        resp.encode_to(&mut data).unwrap();
+        resp.encode_to(&mut data).unwrap();
+        data.extend_from_slice(b"000");
+        let data = data.freeze();
+
+        let (first, rest) = SigmaResponse::decode_one(data).unwrap();
+        assert_eq!(first, resp);
+        let (second, rest) = SigmaResponse::decode_one(rest).unwrap();
+        assert_eq!(second, resp);
+        assert_eq!(&rest[..], b"000");
+        assert!(SigmaResponse::decode_one(rest).is_err());
+
+        let req = SigmaRequest::new("N", "X", "0100", 4007040978).unwrap();
+        let (decoded, rest) = SigmaRequest::decode_one(req.encode().unwrap()).unwrap();
+        assert_eq!(decoded, req);
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn read_from_sync() {
+        let mut src: &[u8] = b"0002401104007040978T\x00\x31\x00\x00\x04849500016YM02000000000001";
+        let resp = SigmaResponse::read_from_sync(&mut src).unwrap();
         assert_eq!(resp.auth_serno, 4007040978);
         assert_eq!(resp.reason, 8495);
-        assert_eq!(resp.xri, Some("X-Request-Id".to_string()));
+        let req = SigmaRequest::read_from_sync(&mut src).unwrap();
+        assert_eq!(req.auth_serno, 1);
+        assert!(src.is_empty());
 
-        let serialized = serde_json::to_string(&resp).unwrap();
+        let mut src: &[u8] = b"0002401104007040978T\x00\x31";
         assert_eq!(
-            serialized,
-            r#"{"mti":"0110","auth_serno":4007040978,"reason":8495,"xri":"X-Request-Id"}"#
+            SigmaResponse::read_from_sync(&mut src),
+            Err(Error::Io(std::io::ErrorKind::UnexpectedEof))
         );
     }
 
     #[test]
-    fn decode_sigma_response_incorrect_auth_serno() {
-        let s = Bytes::from_static(b"000250110XYZ7040978T\x00\x31\x00\x00\x048100");
+    fn encode_batch() {
+        let mut reqs = vec![
+            SigmaRequest::new("N", "X", "0100", 1).unwrap(),
+            SigmaRequest::new("Y", "M", "0200", 2).unwrap(),
+        ];
+        reqs[1].tags.insert(18, "5999".into());
+        reqs[1].iso_fields.insert(2, "4000001234562000".into());
+
+        let data = SigmaRequest::encode_batch(&reqs).unwrap();
+        assert_eq!(
+            data.len(),
+            reqs.iter().map(SigmaRequest::encoded_len).sum::<usize>()
+        );
+        let decoded = SigmaRequest::decode_all(data).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[1].auth_serno, 2);
+        assert_eq!(decoded[1].tags.get(&18).unwrap(), "5999");
 
-        assert!(SigmaResponse::decode(s).is_err());
+        assert!(SigmaRequest::encode_batch(&[]).unwrap().is_empty());
     }
 
     #[test]
-    fn decode_sigma_response_incorrect_reason() {
-        let s = Bytes::from_static(b"0002501104007040978T\x00\x31\x00\x00\x04ABCD");
-
-        assert!(SigmaResponse::decode(s).is_err());
+    fn apply_defaults() {
+        let mut req = SigmaRequest::new("N", "X", "0100", 1).unwrap();
+        req.iso_fields.insert(11, "000001".into());
+        req.tags.insert(7, "X".into());
+
+        let mut defaults = BTreeMap::new();
+        defaults.insert(7, "1016120000".to_string());
+        defaults.insert(11, "999999".to_string());
+        req.apply_defaults(&defaults);
+
+        assert_eq!(req.iso_fields[&7].as_bytes(), b"1016120000");
+        assert_eq!(req.iso_fields[&11].as_bytes(), b"000001");
+        assert_eq!(req.tags.len(), 1);
+        assert!(req.iso_subfields.is_empty());
     }
 
     #[test]
-    fn decode_sigma_response_fee_data() {
-        let s = Bytes::from_static(
-            b"0004001104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x108116978300",
-        );
+    fn iso_amount() {
+        let mut req = SigmaRequest::new("N", "X", "0100", 1).unwrap();
+        req.set_iso_amount(4, 100000000).unwrap();
+        assert_eq!(req.iso_fields[&4].as_bytes(), b"000100000000");
+        assert_eq!(req.iso_amount(4).unwrap(), 100000000);
 
-        let resp = SigmaResponse::decode(s).unwrap();
-        assert_eq!(resp.mti, "0110");
-        assert_eq!(resp.auth_serno, 4007040978);
-        assert_eq!(resp.reason, 8100);
+        assert_eq!(req.iso_amount(6), Err(Error::MissingField("i006".into())));
+        assert!(req.set_iso_amount(6, 1_000_000_000_000).is_err());
+        assert!(!req.has_iso_field(6));
 
-        let serialized = serde_json::to_string(&resp).unwrap();
+        req.iso_fields.insert(6, "0001000 0000".into());
         assert_eq!(
-            serialized,
-            r#"{"mti":"0110","auth_serno":4007040978,"reason":8100,"fees":[{"reason":8116,"currency":978,"amount":300}]}"#
+            req.iso_amount(6).unwrap_err().to_string(),
+            "Incorrect field 'i006', should be amount digits, got '0001000 0000'"
         );
+        req.iso_fields.insert(6, "".into());
+        assert!(req.iso_amount(6).is_err());
     }
 
     #[test]
-    fn decode_sigma_response_correct_short_auth_serno() {
-        let s = Bytes::from_static(b"000240110123123    T\x00\x31\x00\x00\x048100");
+    fn response_json_value() {
+        let mut resp = SigmaResponse::new("0110", 4007040978, 8100).unwrap();
+        resp.fees.push(FeeData {
+            reason: 8116,
+            currency: Currency::Numeric(978),
+            amount: 300,
+        });
+        resp.adata = Some(IsoFieldData::Raw(vec![0xff, 0x01]));
+        resp.xri = Some("X".into());
+        resp.extra_tags.insert(5, "000000010000".into());
+
+        let json = resp.to_json_value();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "MTI": "0110",
+                "Serno": 4007040978u64,
+                "Reason": 8100,
+                "Fees": [{"reason": 8116, "currency": 978, "amount": 300}],
+                "AData": {"raw": "/wE="},
+                "XRI": "X",
+                "T0005": "000000010000",
+            })
+        );
+        assert_eq!(SigmaResponse::from_json_value(json).unwrap(), resp);
 
-        let resp = SigmaResponse::decode(s).unwrap();
-        assert_eq!(resp.mti, "0110");
-        assert_eq!(resp.auth_serno, 123123);
+        let resp = SigmaResponse::from_json_value(
+            serde_json::json!({"MTI": "0110", "Serno": "17", "Reason": "8100", "SupData": "Y"}),
+        )
+        .unwrap();
+        assert_eq!(resp.auth_serno, 17);
         assert_eq!(resp.reason, 8100);
+        assert_eq!(resp.supdata.as_deref(), Some("Y"));
 
-        let serialized = serde_json::to_string(&resp).unwrap();
         assert_eq!(
-            serialized,
-            r#"{"mti":"0110","auth_serno":123123,"reason":8100}"#
+            SigmaResponse::from_json_value(serde_json::json!({"MTI": "0110", "Serno": 1})),
+            Err(Error::MissingField("Reason".into()))
+        );
+        assert!(SigmaResponse::from_json_value(
+            serde_json::json!({"MTI": "0110", "Serno": 1, "Reason": 5000000000u64})
+        )
+        .is_err());
+        assert!(SigmaResponse::from_json_value(
+            serde_json::json!({"MTI": "0110", "Serno": 1, "Reason": 0, "Fees": "8116"})
+        )
+        .is_err());
+        assert_eq!(
+            SigmaResponse::from_json_value(
+                serde_json::json!({"MTI": "0110", "Serno": 1, "Reason": 0, "T0031": "8100"})
+            ),
+            Err(Error::IncorrectData(
+                "Unknown SigmaResponse JSON key 'T0031'".into()
+            ))
         );
     }
 
     #[test]
-    fn decode_fee_data() {
-        let data = b"8116978300";
+    fn decode_with_visitor() {
+        let data = Bytes::from_static(
+            b"00038YM02000000000001I\x00\x02\x00\x00\x0212S\x00\x48\x01\x00\x011S\x00\x48\x02\x00\x012",
+        );
+        let mut fields = Vec::new();
+        let mut bytes = 0;
+        let req = SigmaRequest::decode_with(data.clone(), |tag, data| {
+            fields.push(tag.clone());
+            bytes += data.len();
+        })
+        .unwrap();
 
-        let fee = FeeData::from_slice(data).unwrap();
-        assert_eq!(fee.reason, 8116);
-        assert_eq!(fee.currency, 978);
-        assert_eq!(fee.amount, 300);
+        assert_eq!(req, SigmaRequest::decode(data).unwrap());
+        assert_eq!(
+            fields,
+            vec![
+                Tag::Iso(2),
+                Tag::IsoSubfield(48, 1),
+                Tag::IsoSubfield(48, 2)
+            ]
+        );
+        assert_eq!(bytes, 4);
     }
 
     #[test]
-    fn decode_fee_data_large_amount() {
-        let data = b"8116643123456789";
+    fn inner_mti() {
+        let mut req = SigmaRequest::new("N", "X", "0100", 1).unwrap();
+        assert_eq!(req.inner_mti(), None);
 
-        let fee = FeeData::from_slice(data).unwrap();
-        assert_eq!(fee.reason, 8116);
-        assert_eq!(fee.currency, 643);
-        assert_eq!(fee.amount, 123456789);
+        req.set_inner_mti("0200").unwrap();
+        assert_eq!(req.inner_mti(), Some("0200"));
+        assert_eq!(req.iso_fields[&0].as_bytes(), b"0200");
+
+        assert_eq!(
+            req.set_inner_mti("200").unwrap_err().to_string(),
+            "Incorrect field 'i000', should be 4 digit number (string), got '200'"
+        );
+        assert_eq!(req.inner_mti(), Some("0200"));
     }
 
     #[test]
-    fn decode_sigma_response_fee_data_additional_data() {
-        let s = Bytes::from_static(b"0015201104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x1181166439000T\x00\x48\x00\x01\x05CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI=");
+    fn redact_fields() {
+        let mut req = SigmaRequest::new("N", "X", "0100", 1).unwrap();
+        req.tags.insert(18, "5999".into());
+        req.iso_fields.insert(35, "4000001234562000=2512".into());
+        req.iso_subfields
+            .insert((48, 2), IsoFieldData::Raw(vec![0x01, 0xff, 0x02]));
+        req.iso_fields.insert(4, "000100000000".into());
+
+        let tags = [
+            Tag::Regular(18),
+            Tag::Iso(35),
+            Tag::IsoSubfield(48, 2),
+            Tag::Iso(52),
+        ];
+        let mut masked = req.clone();
+        masked.redact(&tags, RedactMode::Mask);
+        assert_eq!(masked.tag(18), Some("****"));
+        assert_eq!(masked.iso_fields[&35].as_bytes(), &b"*".repeat(21)[..]);
+        assert_eq!(masked.iso_subfields[&(48, 2)].as_bytes(), b"***");
+        assert_eq!(masked.iso_fields[&4].as_bytes(), b"000100000000");
+        assert!(!masked.has_iso_field(52));
+
+        req.redact(&tags, RedactMode::Remove);
+        assert!(req.tags.is_empty());
+        assert_eq!(req.present_iso_fields(), vec![4]);
+        assert!(req.iso_subfields.is_empty());
+
+        let mut req = SigmaRequest::new("N", "X", "0100", 1).unwrap();
+        req.tags.insert(14, "Банк".into());
+        req.iso_fields.insert(43, "ООО Ромашка".into());
+        req.redact(&[Tag::Regular(14), Tag::Iso(43)], RedactMode::Mask);
+        assert_eq!(req.tag(14), Some("****"));
+        assert_eq!(req.iso_fields[&43].as_bytes(), &b"*".repeat(11)[..]);
+    }
 
-        let resp = SigmaResponse::decode(s).unwrap();
-        assert_eq!(resp.mti, "0110");
-        assert_eq!(resp.auth_serno, 4007040978);
-        assert_eq!(resp.reason, 8100);
+    #[test]
+    fn validate_json_keys() {
+        let value = serde_json::json!({
+            "SAF": "N",
+            "SRC": "X",
+            "MTI": "0100",
+            "Serno": 1,
+            "T0018": "5999",
+            "i002": "4000001234562000",
+            "X0001": "1",
+            "i02": "1",
+            "_comment": "test",
+        });
+        let errors = SigmaRequest::validate_json_keys(&value);
+        assert_eq!(
+            errors.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>(),
+            vec!["X0001", "_comment", "i02"]
+        );
+        assert!(errors
+            .iter()
+            .all(|(_, e)| matches!(e, Error::IncorrectTag(_))));
 
-        let serialized = serde_json::to_string(&resp).unwrap();
+        assert!(SigmaRequest::validate_json_keys(&serde_json::json!({"i002": "1"})).is_empty());
         assert_eq!(
-            serialized,
-            r#"{"mti":"0110","auth_serno":4007040978,"reason":8100,"fees":[{"reason":8116,"currency":643,"amount":9000}],"adata":"CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI="}"#
+            SigmaRequest::validate_json_keys(&serde_json::json!([])).len(),
+            1
         );
     }
 
     #[test]
-    fn decode_sigma_response_fee_data_additional_data_supplementary_data() {
-        let s = Bytes::from_static(b"0016101104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x1181166439000T\x00\x48\x00\x01\x05CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI=T\x00\x50\x00\x00\x03123");
+    fn from_json_value_ignore_unknown() {
+        let value = serde_json::json!({
+            "SAF": "N",
+            "SRC": "X",
+            "MTI": "0100",
+            "Serno": 1,
+            "i002": "4000001234562000",
+            "_comment": "test",
+        });
+        assert!(matches!(
+            SigmaRequest::from_json_value(value.clone()),
+            Err(Error::IncorrectTag(_))
+        ));
 
-        let resp = SigmaResponse::decode(s).unwrap();
-        assert_eq!(resp.mti, "0110");
-        assert_eq!(resp.auth_serno, 4007040978);
-        assert_eq!(resp.reason, 8100);
-        //"T0050": "123",
-        let serialized = serde_json::to_string(&resp).unwrap();
-        assert_eq!(
-            serialized,
-            r#"{"mti":"0110","auth_serno":4007040978,"reason":8100,"fees":[{"reason":8116,"currency":643,"amount":9000}],"adata":"CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI=","supdata":"123"}"#
-        );
+        let req = SigmaRequest::from_json_value_with(
+            value,
+            JsonOptions {
+                ignore_unknown: true,
+                ..JsonOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(req.present_iso_fields(), vec![2]);
+        assert!(req.tags.is_empty());
     }
 
     #[test]
-    fn encode_fee_data() {
-        let fee_data = FeeData {
-            reason: 8123,
-            currency: 643,
-            amount: 1234567890,
-        };
+    fn encode_ordered() {
+        let mut req = SigmaRequest::new("Y", "M", "0200", 1).unwrap();
+        req.tags.insert(18, "5999".into());
+        req.iso_fields.insert(2, "12".into());
+        req.iso_subfields.insert((48, 1), "1".into());
+
+        const DEFAULT: &[u8] = b"00041YM02000000000001T\x00\x18\x00\x00\x045999I\x00\x02\x00\x00\x0212S\x00\x48\x01\x00\x011";
+        assert_eq!(req.encode_ordered(&[]).unwrap()[..], DEFAULT[..]);
+        assert_eq!(req.encode().unwrap()[..], DEFAULT[..]);
+
+        let order = [
+            Tag::IsoSubfield(48, 1),
+            Tag::Iso(3),
+            Tag::Iso(2),
+            Tag::IsoSubfield(48, 1),
+        ];
+        let encoded = req.encode_ordered(&order).unwrap();
+        assert_eq!(
+            encoded[..],
+            b"00041YM02000000000001S\x00\x48\x01\x00\x011I\x00\x02\x00\x00\x0212T\x00\x18\x00\x00\x045999"[..]
+        );
+        assert_eq!(SigmaRequest::decode(encoded).unwrap(), req);
 
-        assert_eq!(fee_data.encode().unwrap()[..], b"81236431234567890"[..]);
+        assert_eq!(
+            req.encode_ordered_strict(&order),
+            Err(Error::MissingField("i003".into()))
+        );
+        assert!(req
+            .encode_ordered_strict(&[Tag::Iso(2), Tag::Regular(18)])
+            .is_ok());
+        assert_eq!(req.field_data(&Tag::Regular(18)), Some(&b"5999"[..]));
     }
 
     #[test]
-    fn encode_fee_data_incorrect() {
-        assert!(FeeData {
-            reason: 10000,
-            currency: 643,
-            amount: 1234567890,
-        }
-        .encode()
-        .is_err());
+    fn sigma_request_from_json_with_limits() {
+        let payload = r#"{
+                "SAF": "Y",
+                "SRC": "M",
+                "MTI": "0200",
+                "Serno": 6007040979,
+                "T0000": 2371492071643,
+                "i002": "55555555555555555555"
+            }"#;
+        let mut options = JsonOptions::default();
+        options.max_lengths.insert(Tag::Iso(2), 19);
+        options.max_lengths.insert(Tag::Regular(0), 13);
 
-        assert!(FeeData {
-            reason: 8123,
-            currency: 6430,
-            amount: 1234567890,
-        }
-        .encode()
-        .is_err());
+        assert_eq!(
+            SigmaRequest::from_json_value_with(
+                serde_json::from_str(payload).unwrap(),
+                options.clone()
+            ),
+            Err(Error::incorrect_field_data("i002", "at most 19 characters")
+                .got("55555555555555555555"))
+        );
+
+        options.max_lengths.insert(Tag::Iso(2), 20);
+        let req = SigmaRequest::from_json_value_with(
+            serde_json::from_str(payload).unwrap(),
+            options.clone(),
+        )
+        .unwrap();
+        assert_eq!(req.iso_fields[&2].as_bytes(), b"55555555555555555555");
+
+        options.max_lengths.insert(Tag::Regular(0), 12);
+        options.ignore_unknown = true;
+        let payload = payload.replace("\"MTI\"", "\"_comment\": \"test\", \"MTI\"");
+        assert!(matches!(
+            SigmaRequest::from_json_value_with(serde_json::from_str(&payload).unwrap(), options),
+            Err(Error::IncorrectFieldData { field_name, .. }) if field_name == "T0000"
+        ));
     }
 
     #[test]
-    fn encode_sigma_response_fee_data_additional_data() {
-        let src = r#"{"mti":"0110","auth_serno":4007040978,"reason":8100,"fees":[{"reason":8116,"currency":643,"amount":9000}],"adata":"CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI="}"#;
-        let response = serde_json::from_str::<SigmaResponse>(src).unwrap();
-
-        let target = b"0015201104007040978T\x00\x31\x00\x00\x048100T\x00\x32\x00\x00\x1181166439000T\x00\x48\x00\x01\x05CJyuARCDBRibpKn+BSIVCgx0ZmE6FwAAAKoXmwIQnK4BGLcBIhEKDHRmcDoWAAAAxxX+ARik\nATCBu4PdBToICKqv7BQQgwVAnK4BSAI=";
-        assert_eq!(response.encode().unwrap()[..], target[..])
+    fn sigma_request_encode_stream() {
+        let mut req = SigmaRequest::new("Y", "M", "0200", 4007040978).unwrap();
+        req.set_tag(18, "Y");
+        req.set_iso_field(2, "5555555555554444");
+        req.set_iso_subfield(48, 1, "X");
+
+        let chunks = req.encode_stream().unwrap().collect::<Vec<_>>();
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[0][..], b"00052YM02004007040978"[..]);
+        assert_eq!(chunks[1][..], b"T\x00\x18\x00\x00\x01Y"[..]);
+        assert_eq!(chunks.concat(), req.encode().unwrap().to_vec());
+
+        req.set_iso_field(10000, "X");
+        assert!(matches!(
+            req.encode_stream().err(),
+            Some(Error::TagOutOfRange(Tag::Iso(10000)))
+        ));
     }
 
     #[test]
@@ -1198,6 +4506,36 @@ mod tests {
         assert!(validate_source("").is_err());
         assert!(validate_source("YY").is_err());
         assert!(validate_source("NN").is_err());
+        assert!(validate_source("\x01").is_err());
+        assert!(validate_source(" ").is_ok());
+        assert!(validate_source("\x7f").is_err());
+        assert_eq!(
+            validate_source("Я"),
+            Err(Error::IncorrectFieldData {
+                field_name: "SRC".into(),
+                should_be: "single printable ASCII char".into(),
+                got: Some("\\xd0\\xaf".into()),
+            })
+        );
+        assert!(validate_source("é").is_err());
+    }
+
+    #[test]
+    fn set_source_checked() {
+        let mut r = SigmaRequest::new("N", "M", "0200", 1).unwrap();
+        r.set_source_checked("O", &['M', 'O', 'X', 'P']).unwrap();
+        assert_eq!(r.source(), "O");
+
+        assert_eq!(
+            r.set_source_checked("Z", &['M', 'O', 'X', 'P']),
+            Err(Error::IncorrectFieldData {
+                field_name: "SRC".into(),
+                should_be: "one of M, O, X, P".into(),
+                got: Some("Z".into()),
+            })
+        );
+        assert!(r.set_source_checked("\x00", &['\x00']).is_err());
+        assert_eq!(r.source(), "O");
     }
 
     #[test]
@@ -1208,5 +4546,17 @@ mod tests {
         assert!(validate_mti("120").is_err());
         assert!(validate_mti("00120").is_err());
         assert!(validate_mti("O120").is_err());
+
+        assert_eq!(
+            validate_mti("12000").unwrap_err().to_string(),
+            "Incorrect field 'MTI', should be 4 digit number (string), got '12000'"
+        );
+        assert_eq!(
+            validate_mti(&"\t".repeat(40)).unwrap_err().to_string(),
+            format!(
+                "Incorrect field 'MTI', should be 4 digit number (string), got '{}...'",
+                "\\t".repeat(32)
+            )
+        );
     }
 }
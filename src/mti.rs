@@ -0,0 +1,125 @@
+use core::fmt::{Display, Formatter, Result as FmtResult};
+use core::str::FromStr;
+
+use alloc::format;
+
+use crate::{validate_mti, Error};
+
+/// ISO 8583 message type indicator, split into its four digits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Mti([u8; 4]);
+
+impl Mti {
+    /// ISO 8583 version digit (`0` for 1987, `1` for 1993, `2` for 2003).
+    pub fn version(&self) -> u8 {
+        self.0[0]
+    }
+
+    /// Message class digit, e.g. `1` for authorization and `2` for financial messages.
+    pub fn class(&self) -> u8 {
+        self.0[1]
+    }
+
+    /// Message function digit, e.g. `0` for request and `1` for request response.
+    pub fn function(&self) -> u8 {
+        self.0[2]
+    }
+
+    /// Message origin digit, e.g. `0` for acquirer and `2` for issuer.
+    pub fn origin(&self) -> u8 {
+        self.0[3]
+    }
+
+    /// Whether the message is a request, advice or notification rather than response to one.
+    pub fn is_request(&self) -> bool {
+        self.function() & 1 == 0
+    }
+
+    /// MTI of the response to this message, e.g. `0210` for `0200` and `0130` for repeated advice `0121`.
+    ///
+    /// Only requests (function `0`) and advices (function `2`) have responses, anything else, e.g. notification `0240`
+    /// or a response itself, fails with [`Error::IncorrectData`].
+    pub fn response_mti(&self) -> Result<Mti, Error> {
+        let function = match self.function() {
+            0 => 1,
+            2 => 3,
+            _ => {
+                return Err(Error::IncorrectData(format!(
+                    "MTI {} has no defined response",
+                    self
+                )))
+            }
+        };
+        let mut digits = self.0;
+        digits[2] = function;
+        // Response to a repeat is the same as to the original message
+        digits[3] &= !1;
+        Ok(Mti(digits))
+    }
+}
+
+impl FromStr for Mti {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        validate_mti(s)?;
+        let b = s.as_bytes();
+        Ok(Self([b[0] - b'0', b[1] - b'0', b[2] - b'0', b[3] - b'0']))
+    }
+}
+
+impl Display for Mti {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_fmt(format_args!(
+            "{}{}{}{}",
+            self.0[0], self.0[1], self.0[2], self.0[3]
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mti() {
+        let mti: Mti = "0200".parse().unwrap();
+        assert_eq!(mti.version(), 0);
+        assert_eq!(mti.class(), 2);
+        assert_eq!(mti.function(), 0);
+        assert_eq!(mti.origin(), 0);
+        assert!(mti.is_request());
+        assert_eq!(mti.to_string(), "0200");
+
+        assert!("020".parse::<Mti>().is_err());
+        assert!("O200".parse::<Mti>().is_err());
+    }
+
+    #[test]
+    fn response_mti() {
+        let response = |mti: &str| {
+            mti.parse::<Mti>()
+                .unwrap()
+                .response_mti()
+                .map(|m| m.to_string())
+        };
+        assert_eq!(response("0100").unwrap(), "0110");
+        assert_eq!(response("0200").unwrap(), "0210");
+        assert_eq!(response("0420").unwrap(), "0430");
+        assert_eq!(response("0120").unwrap(), "0130");
+        assert_eq!(response("0121").unwrap(), "0130");
+        assert_eq!(response("0123").unwrap(), "0132");
+
+        assert_eq!(
+            response("0240"),
+            Err(Error::IncorrectData(
+                "MTI 0240 has no defined response".into()
+            ))
+        );
+        assert!(response("0280").is_err());
+
+        let resp: Mti = "0110".parse().unwrap();
+        assert!(!resp.is_request());
+        assert!(resp.response_mti().is_err());
+    }
+}
@@ -0,0 +1,87 @@
+use core::fmt::{Display, Formatter, Result as FmtResult};
+use core::ops::RangeInclusive;
+
+use crate::IsoFieldData;
+
+/// Reason code of [`SigmaResponse`](crate::SigmaResponse).
+///
+/// Which codes approve the transaction depends on the processing host, so the approval ranges are supplied by the
+/// caller, e.g. `&[8100..=8199]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ReasonCode(pub u32);
+
+impl ReasonCode {
+    /// Checks whether the code is in one of `approval_ranges`.
+    pub fn is_approval(&self, approval_ranges: &[RangeInclusive<u32>]) -> bool {
+        approval_ranges.iter().any(|r| r.contains(&self.0))
+    }
+
+    /// Checks whether the code is outside of all `approval_ranges`.
+    pub fn is_decline(&self, approval_ranges: &[RangeInclusive<u32>]) -> bool {
+        !self.is_approval(approval_ranges)
+    }
+}
+
+impl From<u32> for ReasonCode {
+    fn from(v: u32) -> Self {
+        Self(v)
+    }
+}
+
+impl From<ReasonCode> for u32 {
+    fn from(v: ReasonCode) -> Self {
+        v.0
+    }
+}
+
+impl Display for ReasonCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_fmt(format_args!("{}", self.0))
+    }
+}
+
+/// Declined [`SigmaResponse`](crate::SigmaResponse), see [`SigmaResponse::to_result`](crate::SigmaResponse::to_result).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeclineError {
+    pub reason: ReasonCode,
+    /// Additional data (tag 48) of the response, if any.
+    pub adata: Option<IsoFieldData>,
+}
+
+impl Display for DeclineError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_fmt(format_args!("Declined with reason {}", self.reason))
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DeclineError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_reason_code() {
+        let approvals = [8100..=8199, 8300..=8300];
+        assert!(ReasonCode(8100).is_approval(&approvals));
+        assert!(ReasonCode(8199).is_approval(&approvals));
+        assert!(ReasonCode(8300).is_approval(&approvals));
+        assert!(!ReasonCode(8100).is_decline(&approvals));
+
+        assert!(ReasonCode(8495).is_decline(&approvals));
+        assert!(ReasonCode(8099).is_decline(&approvals));
+        assert!(ReasonCode(8200).is_decline(&approvals));
+        assert!(!ReasonCode(8495).is_approval(&approvals));
+        assert!(ReasonCode(8100).is_decline(&[]));
+    }
+
+    #[test]
+    fn decline_error_display() {
+        let err = DeclineError {
+            reason: ReasonCode(8495),
+            adata: None,
+        };
+        assert_eq!(err.to_string(), "Declined with reason 8495");
+    }
+}
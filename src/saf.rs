@@ -0,0 +1,68 @@
+use core::fmt::{Display, Formatter, Result as FmtResult};
+use core::str::FromStr;
+
+use crate::{validate_saf, Error};
+
+/// Store-and-forward flag of [`SigmaRequest`](crate::SigmaRequest), encoded as `Y` or `N` on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Saf {
+    Yes,
+    No,
+}
+
+impl Saf {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Saf::Yes => "Y",
+            Saf::No => "N",
+        }
+    }
+}
+
+impl From<bool> for Saf {
+    fn from(v: bool) -> Self {
+        match v {
+            true => Saf::Yes,
+            false => Saf::No,
+        }
+    }
+}
+
+impl From<Saf> for bool {
+    fn from(v: Saf) -> Self {
+        v == Saf::Yes
+    }
+}
+
+impl FromStr for Saf {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        validate_saf(s)?;
+        Ok((s == "Y").into())
+    }
+}
+
+impl Display for Saf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_saf() {
+        assert_eq!("Y".parse::<Saf>().unwrap(), Saf::Yes);
+        assert_eq!("N".parse::<Saf>().unwrap(), Saf::No);
+        assert!("y".parse::<Saf>().is_err());
+        assert!("".parse::<Saf>().is_err());
+
+        assert_eq!(Saf::from(true).to_string(), "Y");
+        assert_eq!(Saf::from(false).to_string(), "N");
+        assert!(bool::from(Saf::Yes));
+        assert!(!bool::from(Saf::No));
+    }
+}
@@ -0,0 +1,153 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, SigmaRequest, Tag};
+
+/// Constraints of single ISO field in [`Schema`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldRule {
+    #[serde(default)]
+    pub mandatory: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_len: Option<usize>,
+}
+
+/// ISO fields allowed in requests of some kind, e.g. per MTI, see [`SigmaRequest::validate_against`].
+///
+/// It is built either programmatically or deserialized, e.g. from `{"iso_fields": {"2": {"mandatory": true, "max_len": 19}}}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schema {
+    #[serde(default)]
+    pub iso_fields: BTreeMap<u16, FieldRule>,
+    /// Allow ISO fields which are not listed in `iso_fields`, they are reported as violations otherwise.
+    #[serde(default)]
+    pub allow_unlisted: bool,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds ISO field `n` which has to be present.
+    pub fn mandatory(mut self, n: u16, max_len: Option<usize>) -> Self {
+        self.iso_fields.insert(
+            n,
+            FieldRule {
+                mandatory: true,
+                max_len,
+            },
+        );
+        self
+    }
+
+    /// Adds ISO field `n` which may be absent.
+    pub fn optional(mut self, n: u16, max_len: Option<usize>) -> Self {
+        self.iso_fields.insert(
+            n,
+            FieldRule {
+                mandatory: false,
+                max_len,
+            },
+        );
+        self
+    }
+
+    pub fn allow_unlisted(mut self, allow_unlisted: bool) -> Self {
+        self.allow_unlisted = allow_unlisted;
+        self
+    }
+}
+
+impl SigmaRequest {
+    /// Checks presence and lengths of ISO fields according to `schema`, returning all the violations.
+    pub fn validate_against(&self, schema: &Schema) -> Result<(), Vec<Error>> {
+        let mut errors = Vec::new();
+
+        for (n, rule) in &schema.iso_fields {
+            match self.iso_fields.get(n) {
+                None if rule.mandatory => {
+                    errors.push(Error::MissingField(Tag::Iso(*n).to_string()));
+                }
+                Some(data) => match rule.max_len {
+                    Some(max_len) if data.as_bytes().len() > max_len => {
+                        errors.push(Error::Bounds(format!(
+                            "Field {} is {} bytes long, should be at most {}",
+                            Tag::Iso(*n),
+                            data.as_bytes().len(),
+                            max_len
+                        )));
+                    }
+                    _ => {}
+                },
+                None => {}
+            }
+        }
+
+        if !schema.allow_unlisted {
+            for n in self.iso_fields.keys() {
+                if !schema.iso_fields.contains_key(n) {
+                    errors.push(Error::IncorrectData(format!(
+                        "Field {} is not allowed by schema",
+                        Tag::Iso(*n)
+                    )));
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_against_schema() {
+        let schema = Schema::new()
+            .mandatory(2, Some(19))
+            .mandatory(4, Some(12))
+            .optional(49, None);
+
+        let mut req = SigmaRequest::new("N", "X", "0100", 1).unwrap();
+        req.iso_fields.insert(2, "4000001234562000".into());
+        req.iso_fields.insert(4, "000100000000".into());
+        assert_eq!(req.validate_against(&schema), Ok(()));
+
+        req.iso_fields.insert(2, "40000012345620001234".into());
+        req.iso_fields.remove(&4);
+        req.iso_fields.insert(55, "X".into());
+        assert_eq!(
+            req.validate_against(&schema),
+            Err(alloc::vec![
+                Error::Bounds("Field i002 is 20 bytes long, should be at most 19".into()),
+                Error::MissingField("i004".into()),
+                Error::IncorrectData("Field i055 is not allowed by schema".into()),
+            ])
+        );
+
+        let schema = schema.allow_unlisted(true);
+        assert_eq!(req.validate_against(&schema).unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn schema_serde() {
+        let schema: Schema = serde_json::from_str(
+            r#"{"iso_fields": {"2": {"mandatory": true, "max_len": 19}, "49": {}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            schema,
+            Schema::new().mandatory(2, Some(19)).optional(49, None)
+        );
+    }
+}
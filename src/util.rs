@@ -1,6 +1,12 @@
+use alloc::format;
+use alloc::string::{String, ToString};
 use bytes::{Bytes, BytesMut};
+use core::convert::TryFrom;
+use core::fmt::{Display, Formatter, Result as FmtResult, Write};
+use core::str::FromStr;
+#[cfg(feature = "std")]
 use rand::Rng;
-use std::fmt::{Display, Formatter, Result as FmtResult};
+use serde::{Deserialize, Serialize};
 
 use super::Error;
 
@@ -10,6 +16,19 @@ macro_rules! parse_ascii_bytes_lossy {
     };
 }
 
+/// Builds [`Tag`] from its literal, e.g. `tag!(T0031)`, `tag!(i002)` or `tag!(s004801)`, checked at compile time.
+///
+/// ```compile_fail
+/// let _ = extfg_sigma::tag!(i0x2);
+/// ```
+#[macro_export]
+macro_rules! tag {
+    ($tag:ident) => {{
+        const TAG: $crate::Tag = $crate::Tag::parse_const(stringify!($tag));
+        TAG
+    }};
+}
+
 pub(crate) fn bytes_split_to(bytes: &mut Bytes, at: usize) -> Result<Bytes, Error> {
     let len = bytes.len();
 
@@ -24,11 +43,174 @@ pub(crate) fn bytes_split_to(bytes: &mut Bytes, at: usize) -> Result<Bytes, Erro
     Ok(bytes.split_to(at))
 }
 
-/// Generate Authorization Serno
+/// Number of ASCII digits in the message length prefix.
+pub const LENGTH_BYTES_COUNT: usize = 5;
+
+/// Maximum message body length which fits in the length prefix of `len_width` digits.
+fn max_message_len(len_width: usize) -> usize {
+    u32::try_from(len_width)
+        .ok()
+        .and_then(|w| 10usize.checked_pow(w))
+        .map_or(usize::MAX, |v| v - 1)
+}
+
+/// Parses message length prefix of ASCII digits, which may be left-padded with spaces if `trim_leading_spaces` is set.
+fn parse_message_len(len_src: &[u8], trim_leading_spaces: bool) -> Result<usize, Error> {
+    let start = match trim_leading_spaces {
+        true => len_src
+            .iter()
+            .position(|b| *b != b' ')
+            .unwrap_or(len_src.len()),
+        false => 0,
+    };
+    let digits = &len_src[start..];
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(Error::IncorrectMessageLength(len_src.to_vec()));
+    }
+    parse_ascii_bytes_lossy!(
+        digits,
+        usize,
+        Error::IncorrectMessageLength(len_src.to_vec())
+    )
+}
+
+/// Parses length prefix of the message at the beginning of `buf`.
+///
+/// Returns `Ok(None)` if `buf` is shorter than the prefix, otherwise the total length of the message including the prefix.
+pub fn peek_message_len(buf: &[u8]) -> Result<Option<usize>, Error> {
+    peek_message_len_with_width(buf, LENGTH_BYTES_COUNT)
+}
+
+/// Same as [`peek_message_len`], but for length prefix of `len_width` digits.
+pub fn peek_message_len_with_width(buf: &[u8], len_width: usize) -> Result<Option<usize>, Error> {
+    peek_message_len_impl(buf, len_width, false)
+}
+
+/// Same as [`peek_message_len_with_width`], but also accepts length prefix left-padded with spaces if
+/// `trim_leading_spaces` is set.
+pub(crate) fn peek_message_len_impl(
+    buf: &[u8],
+    len_width: usize,
+    trim_leading_spaces: bool,
+) -> Result<Option<usize>, Error> {
+    if buf.len() < len_width {
+        return Ok(None);
+    }
+    Ok(Some(
+        len_width + parse_message_len(&buf[..len_width], trim_leading_spaces)?,
+    ))
+}
+
+/// Formats zero-padded length prefix for message body of `body_len` bytes, the inverse of [`peek_message_len`].
+///
+/// Fails with [`Error::Bounds`] if `body_len` doesn't fit in [`LENGTH_BYTES_COUNT`] digits.
+pub fn format_length_prefix(body_len: usize) -> Result<[u8; LENGTH_BYTES_COUNT], Error> {
+    let max_len = max_message_len(LENGTH_BYTES_COUNT);
+    if body_len > max_len {
+        return Err(Error::Bounds(format!(
+            "Message is {} bytes long, should be at most {}",
+            body_len, max_len
+        )));
+    }
+    let mut prefix = [0u8; LENGTH_BYTES_COUNT];
+    prefix.copy_from_slice(format!("{:0width$}", body_len, width = LENGTH_BYTES_COUNT).as_bytes());
+    Ok(prefix)
+}
+
+/// Splits length prefix of `len_width` digits and the body of the message following it from `data`, returning the body.
+pub(crate) fn split_message_body(
+    data: &mut Bytes,
+    len_width: usize,
+    trim_leading_spaces: bool,
+) -> Result<Bytes, Error> {
+    let msg_len = parse_message_len(&bytes_split_to(data, len_width)?, trim_leading_spaces)?;
+    bytes_split_to(data, msg_len)
+}
+
+/// Allocates buffer for the whole message starting with length `prefix`, the body is to be read after the prefix.
+///
+/// Shared by blocking [`read_message`] and async `codec::read_frame`.
+#[cfg(feature = "std")]
+pub(crate) fn message_buf_for_prefix(
+    prefix: [u8; LENGTH_BYTES_COUNT],
+) -> Result<alloc::vec::Vec<u8>, Error> {
+    let overall_length = peek_message_len(&prefix)?.unwrap_or(LENGTH_BYTES_COUNT);
+
+    let mut message = alloc::vec![0u8; overall_length];
+    message[..LENGTH_BYTES_COUNT].copy_from_slice(&prefix);
+    Ok(message)
+}
+
+/// Reads single length-prefixed message from `r`, including the prefix.
+#[cfg(feature = "std")]
+pub(crate) fn read_message<R: std::io::Read + ?Sized>(r: &mut R) -> Result<Bytes, Error> {
+    let mut len = [0u8; LENGTH_BYTES_COUNT];
+    r.read_exact(&mut len)?;
+
+    let mut message = message_buf_for_prefix(len)?;
+    r.read_exact(&mut message[LENGTH_BYTES_COUNT..])?;
+    Ok(message.into())
+}
+
+/// Appends the message produced by `encode_body` to `buf` prefixed with its length of `len_width` digits.
+///
+/// `buf` is truncated back to its original length on failure.
+pub(crate) fn encode_message_to_buf(
+    buf: &mut BytesMut,
+    len_width: usize,
+    encode_body: impl FnOnce(&mut BytesMut) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let start = buf.len();
+    buf.resize(start + len_width, b'0');
+
+    let res = encode_body(buf).and_then(|_| {
+        let msg_len = buf.len() - start - len_width;
+        let max_len = max_message_len(len_width);
+        if msg_len > max_len {
+            return Err(Error::Bounds(format!(
+                "Message is {} bytes long, should be at most {}",
+                msg_len, max_len
+            )));
+        }
+        buf[start..start + len_width]
+            .copy_from_slice(format!("{:0width$}", msg_len, width = len_width).as_bytes());
+        Ok(())
+    });
+
+    if res.is_err() {
+        buf.truncate(start);
+    }
+    res
+}
+
+/// Number of ASCII digits in the MTI.
+pub const MTI_LEN: usize = 4;
+
+/// Number of ASCII digits in the authorization serno.
+pub const SERNO_LEN: usize = 10;
+
+/// Length of the request header following the length prefix: SAF(1), SRC(1), MTI and serno.
+pub const REQUEST_HEADER_LEN: usize = 1 + 1 + MTI_LEN + SERNO_LEN;
+
+/// Length of the response header following the length prefix: MTI and serno.
+pub const RESPONSE_HEADER_LEN: usize = MTI_LEN + SERNO_LEN;
+
+/// Length of the field tag and data length preceding the field data.
+pub const FIELD_HEADER_LEN: usize = 4 + 2;
+
+/// Maximum authorization serno which fits in its 10 digits on the wire.
+pub const MAX_AUTH_SERNO: u64 = 9999999999;
+
+/// Generate Authorization Serno in `1..=MAX_AUTH_SERNO` range
+#[cfg(feature = "std")]
 pub fn gen_random_auth_serno() -> u64 {
-    let mut rng = rand::thread_rng();
-    let rrn: u64 = rng.gen();
-    rrn
+    gen_random_auth_serno_with(&mut rand::thread_rng())
+}
+
+/// Generate Authorization Serno in `1..=MAX_AUTH_SERNO` range using provided random number generator
+#[cfg(feature = "std")]
+pub fn gen_random_auth_serno_with<R: Rng + ?Sized>(rng: &mut R) -> u64 {
+    rng.gen_range(1..=MAX_AUTH_SERNO)
 }
 
 pub(crate) fn decode_bcd_x2(v: u8) -> Result<u8, Error> {
@@ -87,6 +269,20 @@ pub(crate) fn encode_bcd_x4(v: u16) -> Result<[u8; 2], Error> {
     Ok([left, right])
 }
 
+/// Parses `b[from..to]` as a decimal number in const context, see [`Tag::parse_const`].
+const fn parse_tag_digits(b: &[u8], from: usize, to: usize) -> u32 {
+    let mut v = 0;
+    let mut i = from;
+    while i < to {
+        if !b[i].is_ascii_digit() {
+            panic!("tag number should be ASCII digits");
+        }
+        v = v * 10 + (b[i] - b'0') as u32;
+        i += 1;
+    }
+    v
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Tag {
     Regular(u16),
@@ -94,35 +290,97 @@ pub enum Tag {
     IsoSubfield(u16, u8),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TagKind {
+    Regular,
+    Iso,
+    IsoSubfield,
+}
+
 impl Tag {
-    pub fn encode_to_buf(&self, buf: &mut BytesMut) -> Result<(), Error> {
-        match self {
-            Self::Regular(i) => {
-                buf.extend_from_slice(&b"T"[..]);
-                buf.extend_from_slice(&encode_bcd_x4(*i)?[..]);
-                buf.extend_from_slice(&[0]);
-            }
-            Self::Iso(i) => {
-                buf.extend_from_slice(&b"I"[..]);
-                buf.extend_from_slice(&encode_bcd_x4(*i)?[..]);
-                buf.extend_from_slice(&[0]);
-            }
-            Self::IsoSubfield(i, si) => {
-                buf.extend_from_slice(&b"S"[..]);
-                buf.extend_from_slice(&encode_bcd_x4(*i)?[..]);
-                buf.extend_from_slice(&[encode_bcd_x2(*si)?]);
+    /// Parses tag from its string representation, e.g. `T0031`, `i002` or `s004801`, same as [`FromStr`].
+    ///
+    /// Kept inherent so that `Tag::from_str` works without importing the trait.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Result<Self, Error> {
+        <Self as FromStr>::from_str(s)
+    }
+
+    /// Parses the tag same as `Tag::from_str`, but only ASCII digits are accepted and it panics on incorrect tag.
+    /// Evaluated in a constant by [`tag!`] it fails the build instead.
+    pub const fn parse_const(s: &str) -> Tag {
+        let b = s.as_bytes();
+        match b {
+            [b'T' | b't', _, _, _, _] => Tag::Regular(parse_tag_digits(b, 1, 5) as u16),
+            [b'I' | b'i', _, _, _] | [b'I' | b'i', _, _, _, _] => {
+                Tag::Iso(parse_tag_digits(b, 1, b.len()) as u16)
             }
+            [b'S' | b's', _, _, _, _, _, _] => Tag::IsoSubfield(
+                parse_tag_digits(b, 1, 5) as u16,
+                parse_tag_digits(b, 5, 7) as u8,
+            ),
+            _ => panic!("tag should be Tnnnn, innn, innnn or snnnnnn"),
         }
-        Ok(())
     }
 
-    pub fn decode(data: Bytes) -> Result<Self, Error> {
-        if data.len() < 4 {
-            return Err(Error::IncorrectTag("Should be 5 bytes long".into()));
+    pub fn kind(&self) -> TagKind {
+        match self {
+            Self::Regular(_) => TagKind::Regular,
+            Self::Iso(_) => TagKind::Iso,
+            Self::IsoSubfield(_, _) => TagKind::IsoSubfield,
+        }
+    }
+
+    /// Tag number or ISO field number.
+    pub fn number(&self) -> u16 {
+        match self {
+            Self::Regular(i) | Self::Iso(i) | Self::IsoSubfield(i, _) => *i,
         }
+    }
+
+    /// Key of the tag in JSON representation, which is always parsed back by `from_str`.
+    ///
+    /// It is same as `Display`: `T` followed by 4 digits for regular tags, `i` followed by 3 digits (4 above 999)
+    /// for ISO fields and `s` followed by 4 + 2 digits for ISO subfields. `from_str` accepts the other case of the
+    /// prefix too.
+    pub fn canonical_key(&self) -> String {
+        self.to_string()
+    }
+
+    /// ISO subfield number for [`Tag::IsoSubfield`].
+    pub fn subfield(&self) -> Option<u8> {
+        match self {
+            Self::IsoSubfield(_, si) => Some(*si),
+            _ => None,
+        }
+    }
+
+    pub fn encode_to_buf(&self, buf: &mut BytesMut) -> Result<(), Error> {
+        buf.extend_from_slice(&self.to_wire()?);
+        Ok(())
+    }
+
+    /// 4 byte wire representation of the tag: kind byte, BCD number and BCD subfield number, e.g. `S\x00\x48\x01`.
+    pub fn to_wire(&self) -> Result<[u8; 4], Error> {
+        self.to_wire_impl()
+            .map_err(|_| Error::TagOutOfRange(self.clone()))
+    }
+
+    fn to_wire_impl(&self) -> Result<[u8; 4], Error> {
+        let (kind, i, si) = match self {
+            Self::Regular(i) => (b'T', i, 0),
+            Self::Iso(i) => (b'I', i, 0),
+            Self::IsoSubfield(i, si) => (b'S', i, encode_bcd_x2(*si)?),
+        };
+        let [i0, i1] = encode_bcd_x4(*i)?;
+        Ok([kind, i0, i1, si])
+    }
+
+    /// Parses 4 byte wire representation of the tag, accepting lowercase kind byte used by some peers.
+    pub fn from_wire(data: &[u8; 4]) -> Result<Self, Error> {
         let i = decode_bcd_x4(&[data[1], data[2]])?;
         let si = decode_bcd_x2(data[3])?;
-        match data[0] {
+        match data[0].to_ascii_uppercase() {
             b'T' => Ok(Tag::Regular(i)),
             b'I' => Ok(Tag::Iso(i)),
             b'S' => Ok(Tag::IsoSubfield(i, si)),
@@ -130,7 +388,19 @@ impl Tag {
         }
     }
 
-    pub fn from_str(s: &str) -> Result<Self, Error> {
+    /// Decodes tag from its wire representation, see [`Tag::from_wire`].
+    pub fn decode(data: Bytes) -> Result<Self, Error> {
+        if data.len() < 4 {
+            return Err(Error::IncorrectTag("Should be 4 bytes long".into()));
+        }
+        Self::from_wire(&[data[0], data[1], data[2], data[3]])
+    }
+}
+
+impl FromStr for Tag {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         let bytes = s.as_bytes();
         match (bytes.first(), s.len()) {
             (Some(b'T'), 5) | (Some(b't'), 5) => {
@@ -141,9 +411,10 @@ impl Tag {
                 )?;
                 Ok(Self::Regular(v))
             }
-            (Some(b'I'), 4) | (Some(b'i'), 4) => {
+            // ISO fields above 999 are displayed with 4 digits
+            (Some(b'I'), 4) | (Some(b'i'), 4) | (Some(b'I'), 5) | (Some(b'i'), 5) => {
                 let v = parse_ascii_bytes_lossy!(
-                    &bytes[1..4],
+                    &bytes[1..],
                     u16,
                     Error::IncorrectTag("incorrect format for i".into())
                 )?;
@@ -171,6 +442,7 @@ impl Tag {
     }
 }
 
+/// Formats the tag as its [`Tag::canonical_key`], e.g. `T0031`, `i002` or `s004801`.
 impl Display for Tag {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         match self {
@@ -181,24 +453,184 @@ impl Display for Tag {
     }
 }
 
+/// Tag is serialized as its `Display` string, e.g. `"T0031"`, `"i002"` or `"s004801"`.
+impl Serialize for Tag {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Tag {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
 pub fn encode_field_to_buf(tag: Tag, data: &[u8], buf: &mut BytesMut) -> Result<(), Error> {
-    tag.encode_to_buf(buf)?;
-    buf.extend_from_slice(&encode_bcd_x4(data.len() as u16)?[..]);
+    buf.extend_from_slice(&encode_field_header(&tag, data.len())?);
     buf.extend_from_slice(data);
     Ok(())
 }
 
+/// Encodes the tag and BCD length of field data of `data_len` bytes, which precede the data itself.
+pub(crate) fn encode_field_header(
+    tag: &Tag,
+    data_len: usize,
+) -> Result<[u8; FIELD_HEADER_LEN], Error> {
+    if data_len > 9999 {
+        return Err(Error::Bounds(format!(
+            "Field {} data is {} bytes long, should be at most 9999",
+            tag, data_len
+        )));
+    }
+    let [t0, t1, t2, t3] = tag.to_wire()?;
+    let [l0, l1] = encode_bcd_x4(data_len as u16)?;
+    Ok([t0, t1, t2, t3, l0, l1])
+}
+
+/// Checks that `auth_serno` fits in 10 digits of the message header.
+pub(crate) fn validate_auth_serno(auth_serno: u64) -> Result<(), Error> {
+    if auth_serno > MAX_AUTH_SERNO {
+        return Err(Error::Bounds(format!(
+            "auth_serno '{}' contains more than 10 digits",
+            auth_serno
+        )));
+    }
+    Ok(())
+}
+
+/// Writes `auth_serno` as 10 zero-padded digits.
+///
+/// Longer values are either cut to their first 10 digits if `truncate` is set, or rejected with [`Error::Bounds`].
+pub(crate) fn encode_auth_serno_to_buf(
+    auth_serno: u64,
+    truncate: bool,
+    buf: &mut BytesMut,
+) -> Result<(), Error> {
+    if auth_serno > MAX_AUTH_SERNO {
+        if !truncate {
+            validate_auth_serno(auth_serno)?;
+        }
+        buf.extend_from_slice(&format!("{}", auth_serno).as_bytes()[..SERNO_LEN]);
+    } else {
+        buf.extend_from_slice(format!("{:0width$}", auth_serno, width = SERNO_LEN).as_bytes());
+    }
+    Ok(())
+}
+
+/// Parses `auth_serno` of the message header: digits, which may be right-padded with spaces.
+pub(crate) fn decode_auth_serno(src: &[u8]) -> Result<u64, Error> {
+    let digits_len = src
+        .iter()
+        .rposition(|b| *b != b' ')
+        .map_or(0, |pos| pos + 1);
+    let digits = &src[..digits_len];
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(Error::incorrect_field_data(
+            "Serno",
+            "digits, optionally right-padded with spaces",
+        )
+        .got(src));
+    }
+    parse_ascii_bytes_lossy!(
+        digits,
+        u64,
+        Error::incorrect_field_data("Serno", "u64").got(src)
+    )
+}
+
 pub fn decode_field_from_cursor(buf: &mut Bytes) -> Result<(Tag, Bytes), Error> {
-    let tag_src = bytes_split_to(buf, 4)?;
-    let tag = Tag::decode(tag_src)?;
+    decode_field_impl(buf, None)
+}
+
+/// Same as [`decode_field_from_cursor`], but failures are wrapped in [`Error::DecodeAt`].
+///
+/// `offset` is the position of `buf` start within the message body, the reported offset is the one of the tag, the
+/// length or the data of the field, whichever failed to decode.
+pub fn decode_field_from_cursor_at(buf: &mut Bytes, offset: usize) -> Result<(Tag, Bytes), Error> {
+    decode_field_impl(buf, Some(offset))
+}
 
-    let len_src = bytes_split_to(buf, 2)?;
-    let len = decode_bcd_x4(&[len_src[0], len_src[1]])?;
+fn decode_field_impl(buf: &mut Bytes, offset: Option<usize>) -> Result<(Tag, Bytes), Error> {
+    let at = |pos: usize| {
+        move |e: Error| match offset {
+            Some(offset) => e.at(offset + pos),
+            None => e,
+        }
+    };
+
+    let tag_src = bytes_split_to(buf, 4).map_err(at(0))?;
+    let tag = Tag::decode(tag_src).map_err(at(0))?;
+
+    let len_src = bytes_split_to(buf, 2).map_err(at(4))?;
+    let len = decode_bcd_x4(&[len_src[0], len_src[1]]).map_err(at(4))?;
 
-    let data = bytes_split_to(buf, len as usize)?;
+    let data = bytes_split_to(buf, len as usize).map_err(at(6))?;
     Ok((tag, data))
 }
 
+/// Counts fields in message `body` following the header, checking tags and lengths without copying the data.
+pub fn count_fields(body: &[u8]) -> Result<usize, Error> {
+    let mut rest = body;
+    let mut count = 0;
+    while !rest.is_empty() {
+        let offset = body.len() - rest.len();
+        if rest.len() < FIELD_HEADER_LEN {
+            return Err(Error::Bounds(format!(
+                "Field header should be {} bytes long, got {}",
+                FIELD_HEADER_LEN,
+                rest.len()
+            ))
+            .at(offset));
+        }
+        if !matches!(rest[0].to_ascii_uppercase(), b'T' | b'I' | b'S') {
+            return Err(Error::IncorrectTag("Unknown kind".into()).at(offset));
+        }
+        decode_bcd_x4(&[rest[1], rest[2]])
+            .and_then(|_| decode_bcd_x2(rest[3]))
+            .map_err(|e| e.at(offset))?;
+        let len = decode_bcd_x4(&[rest[4], rest[5]]).map_err(|e| e.at(offset + 4))? as usize;
+        if rest.len() < FIELD_HEADER_LEN + len {
+            return Err(Error::Bounds(format!(
+                "Field data should be {} bytes long, got {}",
+                len,
+                rest.len() - FIELD_HEADER_LEN
+            ))
+            .at(offset + FIELD_HEADER_LEN));
+        }
+        rest = &rest[FIELD_HEADER_LEN + len..];
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Formats `bytes` like `xxd` does: offset, 16 bytes in hex and the same bytes as ASCII, non-printable ones as `.`.
+pub fn hexdump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let _ = write!(out, "{:08x}:", i * 16);
+        for j in 0..16 {
+            if j % 2 == 0 {
+                out.push(' ');
+            }
+            match line.get(j) {
+                Some(b) => {
+                    let _ = write!(out, "{:02x}", b);
+                }
+                None => out.push_str("  "),
+            }
+        }
+        out.push_str("  ");
+        out.extend(line.iter().map(|b| match b {
+            b' '..=b'~' => *b as char,
+            _ => '.',
+        }));
+        out.push('\n');
+    }
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use bytes::BytesMut;
@@ -324,6 +756,28 @@ mod tests {
         assert_eq!(buf, b"S\x00\x19\x22"[..]);
     }
 
+    #[test]
+    fn tag_wire_roundtrip() {
+        for (tag, wire) in [
+            (Tag::Regular(9), b"T\x00\x09\x00"),
+            (Tag::Regular(19), b"T\x00\x19\x00"),
+            (Tag::Iso(19), b"I\x00\x19\x00"),
+            (Tag::Iso(191), b"I\x01\x91\x00"),
+            (Tag::IsoSubfield(19, 2), b"S\x00\x19\x02"),
+            (Tag::IsoSubfield(19, 22), b"S\x00\x19\x22"),
+        ] {
+            assert_eq!(&tag.to_wire().unwrap(), wire);
+            assert_eq!(Tag::from_wire(wire), Ok(tag));
+        }
+
+        assert_eq!(
+            Tag::Iso(10000).to_wire(),
+            Err(Error::TagOutOfRange(Tag::Iso(10000)))
+        );
+        assert!(Tag::from_wire(b"T\x00\x1A\x00").is_err());
+        assert!(Tag::from_wire(b"X\x00\x19\x00").is_err());
+    }
+
     #[test]
     fn encode_field() {
         let mut buf = BytesMut::new();
@@ -338,6 +792,22 @@ mod tests {
         assert_eq!(buf, b"I\x00\x09\x00\x00\x00"[..]);
     }
 
+    #[test]
+    fn encode_field_too_long() {
+        let mut buf = BytesMut::new();
+        encode_field_to_buf(Tag::Regular(48), &[b'A'; 9999], &mut buf).unwrap();
+        assert_eq!(buf.len(), 6 + 9999);
+
+        let mut buf = BytesMut::new();
+        assert_eq!(
+            encode_field_to_buf(Tag::Regular(48), &[b'A'; 70000], &mut buf),
+            Err(Error::Bounds(
+                "Field T0048 data is 70000 bytes long, should be at most 9999".into()
+            ))
+        );
+        assert!(buf.is_empty());
+    }
+
     #[test]
     fn decode_field() {
         let mut buf = Bytes::from_static(b"T\x00\x09\x00\x00\x05IDDQD");
@@ -353,4 +823,251 @@ mod tests {
         assert_eq!(tag, Tag::Iso(9));
         assert_eq!(data[..], b""[..]);
     }
+
+    #[test]
+    fn decode_field_incorrect_length_offset() {
+        let mut buf = Bytes::from_static(b"T\x00\x09\x00\x00\x0AIDDQD");
+        assert_eq!(
+            decode_field_from_cursor_at(&mut buf, 14),
+            Err(Error::DecodeAt {
+                offset: 18,
+                source: Box::new(Error::Bounds("Right bits is not in [0,9] range: A".into())),
+            })
+        );
+    }
+
+    #[test]
+    fn decode_field_short_data_offset() {
+        let mut buf = Bytes::from_static(b"T\x00\x09\x00\x00\x06IDDQD");
+        assert!(matches!(
+            decode_field_from_cursor_at(&mut buf, 14),
+            Err(Error::DecodeAt { offset: 20, .. })
+        ));
+    }
+
+    #[test]
+    fn tag_string_roundtrip() {
+        for tag in [Tag::Regular(33), Tag::Iso(2), Tag::IsoSubfield(48, 1)] {
+            assert_eq!(Tag::from_str(&tag.to_string()), Ok(tag));
+        }
+
+        assert_eq!(Tag::IsoSubfield(48, 1).to_string(), "s004801");
+        assert_eq!(Tag::from_str("S004801"), Ok(Tag::IsoSubfield(48, 1)));
+        assert_eq!(Tag::from_str("s004801"), Ok(Tag::IsoSubfield(48, 1)));
+
+        for (tag, key) in [
+            (Tag::Regular(0), "T0000"),
+            (Tag::Regular(9999), "T9999"),
+            (Tag::Iso(0), "i000"),
+            (Tag::Iso(1000), "i1000"),
+            (Tag::IsoSubfield(48, 1), "s004801"),
+            (Tag::IsoSubfield(9999, 99), "s999999"),
+        ] {
+            assert_eq!(tag.canonical_key(), key);
+            assert_eq!(Tag::from_str(key), Ok(tag.clone()));
+            assert_eq!(Tag::from_str(&key.to_ascii_uppercase()), Ok(tag.clone()));
+            assert_eq!(Tag::from_str(&key.to_ascii_lowercase()), Ok(tag));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn gen_auth_serno_fits_10_digits() {
+        for _ in 0..1000 {
+            let serno = gen_random_auth_serno();
+            assert!((1..=MAX_AUTH_SERNO).contains(&serno));
+        }
+    }
+
+    #[test]
+    fn peek_len() {
+        assert_eq!(peek_message_len(b""), Ok(None));
+        assert_eq!(peek_message_len(b"0002"), Ok(None));
+        assert_eq!(peek_message_len(b"00024"), Ok(Some(29)));
+        assert_eq!(peek_message_len(b"000240110"), Ok(Some(29)));
+        assert_eq!(
+            peek_message_len(b"0002X"),
+            Err(Error::IncorrectMessageLength(b"0002X".to_vec()))
+        );
+        assert!(peek_message_len(b"   24").is_err());
+        assert_eq!(peek_message_len_impl(b"   24", 5, true), Ok(Some(29)));
+        assert_eq!(
+            peek_message_len_impl(b"24   ", 5, true),
+            Err(Error::IncorrectMessageLength(b"24   ".to_vec()))
+        );
+        assert!(peek_message_len(b"24   ").is_err());
+        assert!(peek_message_len_impl(b"123 4", 5, true).is_err());
+        assert!(peek_message_len_impl(b"     ", 5, true).is_err());
+        assert!(peek_message_len(b"123 4").is_err());
+        assert!(peek_message_len(b"     ").is_err());
+        assert!(peek_message_len(b"+0024").is_err());
+        assert_eq!(
+            Error::IncorrectMessageLength(b"12\x004".to_vec()).to_string(),
+            "Message length should be ASCII digits, got b\"12\\04\""
+        );
+
+        assert_eq!(peek_message_len_with_width(b"002", 4), Ok(None));
+        assert_eq!(peek_message_len_with_width(b"0024", 4), Ok(Some(28)));
+        assert_eq!(peek_message_len_with_width(b"000024", 6), Ok(Some(30)));
+    }
+
+    #[test]
+    fn tag_accessors() {
+        for (src, kind, number, subfield) in [
+            ("T0033", TagKind::Regular, 33, None),
+            ("i002", TagKind::Iso, 2, None),
+            ("s004801", TagKind::IsoSubfield, 48, Some(1)),
+        ] {
+            let tag = Tag::from_str(src).unwrap();
+            assert_eq!(tag.kind(), kind);
+            assert_eq!(tag.number(), number);
+            assert_eq!(tag.subfield(), subfield);
+            assert_eq!(tag.to_string(), src);
+        }
+    }
+
+    #[test]
+    fn encode_tag_out_of_range() {
+        let mut buf = BytesMut::new();
+        assert_eq!(
+            Tag::Regular(10000).encode_to_buf(&mut buf),
+            Err(Error::TagOutOfRange(Tag::Regular(10000)))
+        );
+        assert_eq!(
+            Tag::IsoSubfield(48, 100).encode_to_buf(&mut buf),
+            Err(Error::TagOutOfRange(Tag::IsoSubfield(48, 100)))
+        );
+        assert_eq!(
+            Error::TagOutOfRange(Tag::Regular(10000)).to_string(),
+            "Tag number out of range: T10000"
+        );
+    }
+
+    #[test]
+    fn hexdump_message() {
+        assert_eq!(hexdump(b""), "");
+        assert_eq!(
+            hexdump(b"0002401104007040978T\x00\x31\x00\x00\x048495"),
+            "00000000: 3030 3032 3430 3131 3034 3030 3730 3430  0002401104007040\n\
+             00000010: 3937 3854 0031 0000 0438 3439 35         978T.1...8495\n"
+        );
+    }
+
+    #[test]
+    fn tag_serde() {
+        use alloc::collections::BTreeMap;
+
+        let mut map = BTreeMap::new();
+        map.insert(Tag::Regular(31), 1);
+        map.insert(Tag::Iso(2), 2);
+        map.insert(Tag::IsoSubfield(48, 1), 3);
+        let json = serde_json::to_string(&map).unwrap();
+        assert_eq!(json, r#"{"T0031":1,"i002":2,"s004801":3}"#);
+        assert_eq!(
+            serde_json::from_str::<BTreeMap<Tag, i32>>(&json).unwrap(),
+            map
+        );
+        assert!(serde_json::from_str::<Tag>(r#""X0031""#).is_err());
+    }
+
+    #[test]
+    fn field_length_roundtrip() {
+        let data = vec![b'7'; 9999];
+        let mut buf = BytesMut::new();
+        for len in 0..=9999 {
+            buf.clear();
+            encode_field_to_buf(Tag::Regular(48), &data[..len], &mut buf).unwrap();
+            assert_eq!(buf.len(), FIELD_HEADER_LEN + len);
+
+            let mut src = buf.clone().freeze();
+            let (tag, decoded) = decode_field_from_cursor(&mut src).unwrap();
+            assert_eq!(tag, Tag::Regular(48));
+            assert_eq!(decoded.len(), len);
+            assert!(src.is_empty());
+        }
+
+        buf.clear();
+        assert!(encode_field_to_buf(Tag::Regular(48), &[b'7'; 10000], &mut buf).is_err());
+    }
+
+    #[test]
+    fn count_message_fields() {
+        assert_eq!(count_fields(b""), Ok(0));
+        assert_eq!(
+            count_fields(b"T\x00\x31\x00\x00\x048100I\x00\x02\x00\x00\x0212S\x00\x48\x01\x00\x00"),
+            Ok(3)
+        );
+
+        assert!(matches!(
+            count_fields(b"T\x00\x31\x00\x00\x048100T\x00\x31"),
+            Err(Error::DecodeAt { offset: 10, .. })
+        ));
+        assert!(matches!(
+            count_fields(b"T\x00\x31\x00\x00\x048100T\x00\x31\x00\x00\x04810"),
+            Err(Error::DecodeAt { offset: 16, .. })
+        ));
+        assert!(matches!(
+            count_fields(b"X\x00\x31\x00\x00\x048100"),
+            Err(Error::DecodeAt { offset: 0, .. })
+        ));
+        assert!(matches!(
+            count_fields(b"T\x00\x31\x00\x00\x4A8100"),
+            Err(Error::DecodeAt { offset: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn decode_lowercase_tag_kind() {
+        for (src, tag) in [
+            (&b"t\x00\x31\x00"[..], Tag::Regular(31)),
+            (&b"i\x00\x02\x00"[..], Tag::Iso(2)),
+            (&b"s\x00\x48\x01"[..], Tag::IsoSubfield(48, 1)),
+        ] {
+            assert_eq!(Tag::decode(Bytes::from_static(src)), Ok(tag.clone()));
+
+            let mut buf = BytesMut::new();
+            tag.encode_to_buf(&mut buf).unwrap();
+            assert_eq!(buf[0], src[0].to_ascii_uppercase());
+        }
+
+        let mut field = Bytes::from_static(b"t\x00\x31\x00\x00\x048100");
+        assert_eq!(
+            decode_field_from_cursor(&mut field).unwrap(),
+            (Tag::Regular(31), Bytes::from_static(b"8100"))
+        );
+        assert_eq!(count_fields(b"t\x00\x31\x00\x00\x048100"), Ok(1));
+        assert!(Tag::decode(Bytes::from_static(b"x\x00\x31\x00")).is_err());
+    }
+
+    #[test]
+    fn tag_macro() {
+        assert_eq!(tag!(T0031), Tag::Regular(31));
+        assert_eq!(tag!(i002), Tag::Iso(2));
+        assert_eq!(tag!(I1000), Tag::Iso(1000));
+        assert_eq!(tag!(s004801), Tag::IsoSubfield(48, 1));
+        for s in ["T0031", "i002", "i1000", "s004801"] {
+            assert_eq!(Tag::parse_const(s), Tag::from_str(s).unwrap());
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn tag_parse_const_incorrect() {
+        Tag::parse_const("i0x2");
+    }
+
+    #[test]
+    fn length_prefix_roundtrip() {
+        assert_eq!(format_length_prefix(0).unwrap(), *b"00000");
+        assert_eq!(format_length_prefix(24).unwrap(), *b"00024");
+        assert_eq!(format_length_prefix(99999).unwrap(), *b"99999");
+        assert!(matches!(
+            format_length_prefix(100000),
+            Err(Error::Bounds(_))
+        ));
+
+        let mut msg = format_length_prefix(4).unwrap().to_vec();
+        msg.extend_from_slice(b"body");
+        assert_eq!(peek_message_len(&msg).unwrap(), Some(msg.len()));
+    }
 }